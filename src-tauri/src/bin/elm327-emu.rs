@@ -0,0 +1,342 @@
+// ELM327 emulator serving the Soul EV BMS protocol over TCP, for developing the UI without a car.
+//
+// Usage: elm327-emu [--listen 127.0.0.1:50059] [--scenario NAME[=VALUE]]... [--script FILE]
+//
+// Scenarios:
+//   idle                  car parked, ignition on
+//   charging[=KW]         charging ramp at the given power, 6.6 kW (J1772) by default, CHAdeMO above 7 kW
+//   driving               driving with random load spikes and regen
+//   weak-cell[=CELL]      one cell (1-based, 37 by default) slowly drifts below the others
+//   drop-frames[=P]       drop each consecutive frame of a multi-frame reply with probability P
+//   no-data[=P]           answer OBD requests with NO DATA with probability P
+//
+// A script file switches scenarios over time, one `SECONDS SCENARIO[=VALUE]` per line:
+//   0 charging=50
+//   600 driving
+//   900 no-data=0.1
+
+#[allow(dead_code)]
+#[path = "../kia/simulator/emulator.rs"]
+mod emulator;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+use log::{error, info, warn};
+use rand::Rng;
+
+use emulator::{Emulator, PackState, CELL_COUNT};
+
+const TICK: Duration = Duration::from_millis(200);
+const MONITOR_INTERVAL: Duration = Duration::from_millis(100);
+const PACK_CAPACITY_AH: f64 = 75.0;
+const CELL_RESISTANCE: f64 = 0.0008;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Idle,
+    Charging { power_kw: f64 },
+    Driving,
+}
+
+#[derive(Debug, Clone)]
+struct Simulation {
+    mode: Mode,
+    weak_cell: Option<usize>,
+    cell_offsets: [f64; CELL_COUNT],
+    drop_frames: f64,
+    no_data: f64,
+    /// Time not yet added to the operating time, the BMS counts whole seconds
+    uncounted_time: Duration,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut cell_offsets = [0.0; CELL_COUNT];
+        for offset in cell_offsets.iter_mut() {
+            *offset = rng.gen_range(-0.01..0.01);
+        }
+
+        Self {
+            mode: Mode::Idle,
+            weak_cell: None,
+            cell_offsets,
+            drop_frames: 0.0,
+            no_data: 0.0,
+            uncounted_time: Duration::ZERO,
+        }
+    }
+}
+
+impl Simulation {
+    fn apply(&mut self, scenario: &str) -> Result<(), String> {
+        let (name, value) = match scenario.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (scenario, None),
+        };
+        let number = |default: f64| -> Result<f64, String> {
+            value
+                .map(|v| v.parse::<f64>().map_err(|e| format!("bad value for {}: {}", name, e)))
+                .unwrap_or(Ok(default))
+        };
+
+        match name {
+            "idle" => self.mode = Mode::Idle,
+            "charging" => self.mode = Mode::Charging { power_kw: number(6.6)? },
+            "driving" => self.mode = Mode::Driving,
+            "weak-cell" => {
+                let cell = number(37.0)? as usize;
+                if cell == 0 || cell > CELL_COUNT {
+                    return Err(format!("weak-cell must be between 1 and {}", CELL_COUNT));
+                }
+                self.weak_cell = Some(cell - 1);
+            }
+            "drop-frames" => self.drop_frames = number(0.05)?,
+            "no-data" => self.no_data = number(0.05)?,
+            _ => return Err(format!("unknown scenario {}", name)),
+        }
+        info!("Scenario {} applied", scenario);
+
+        Ok(())
+    }
+
+    fn tick(&mut self, pack: &mut PackState, dt: Duration) {
+        let mut rng = rand::thread_rng();
+        self.uncounted_time += dt;
+        let dt = dt.as_secs_f64();
+
+        pack.charging = false;
+        pack.chademo_plugged = false;
+        pack.j1772_plugged = false;
+        pack.motor_rpm = 0;
+        pack.battery_current = match self.mode {
+            Mode::Idle => 0.5 + rng.gen_range(-0.2..0.2),
+            Mode::Charging { power_kw } => {
+                pack.charging = pack.state_of_charge < 100.0;
+                pack.chademo_plugged = power_kw > 7.0;
+                pack.j1772_plugged = !pack.chademo_plugged;
+                // taper above 80% like the real BMS does
+                let taper = if pack.state_of_charge > 80.0 { (100.0 - pack.state_of_charge) / 20.0 } else { 1.0 };
+                if pack.charging {
+                    -power_kw * 1000.0 * taper / pack.battery_dc_voltage()
+                } else {
+                    0.0
+                }
+            }
+            Mode::Driving => {
+                pack.motor_rpm = rng.gen_range(2000..6000);
+                match rng.gen_range(0..20) {
+                    0 => rng.gen_range(150.0..280.0),
+                    1 => -rng.gen_range(20.0..80.0),
+                    _ => rng.gen_range(15.0..45.0),
+                }
+            }
+        };
+
+        let amp_hours = pack.battery_current * dt / 3600.0;
+        let kilowatt_hours = amp_hours * pack.battery_dc_voltage() / 1000.0;
+        if amp_hours > 0.0 {
+            pack.cumulative_discharge_current += amp_hours;
+            pack.cumulative_energy_discharged += kilowatt_hours;
+        } else {
+            pack.cumulative_charge_current -= amp_hours;
+            pack.cumulative_energy_charged -= kilowatt_hours;
+        }
+        pack.state_of_charge = (pack.state_of_charge - amp_hours / PACK_CAPACITY_AH * 100.0).clamp(0.0, 100.0);
+        let seconds = self.uncounted_time.as_secs();
+        pack.operating_time += seconds as u32;
+        self.uncounted_time -= Duration::from_secs(seconds);
+
+        if let Some(cell) = self.weak_cell {
+            self.cell_offsets[cell] -= 0.0005 * dt;
+        }
+        let open_circuit_voltage = 3.5 + 0.0065 * pack.state_of_charge;
+        for (voltage, offset) in pack.cell_voltages.iter_mut().zip(self.cell_offsets.iter()) {
+            *voltage = (open_circuit_voltage - pack.battery_current * CELL_RESISTANCE + offset).clamp(2.5, 4.2);
+        }
+
+        let heat = pack.battery_current.abs() / 150.0 * dt;
+        for temperature in pack.module_temperatures.iter_mut() {
+            if heat > 0.05 && rng.gen_bool(heat.min(1.0)) {
+                *temperature = (*temperature + 1).min(55);
+            } else if rng.gen_bool((0.01 * dt).min(1.0)) && *temperature > 20 {
+                *temperature -= 1;
+            }
+        }
+    }
+
+    /// Applies the fault scenarios to one reply of the emulator
+    fn distort(&self, command: &str, reply: String) -> String {
+        let mut rng = rand::thread_rng();
+        let is_request = !command.trim().to_uppercase().starts_with("AT") && !command.trim().is_empty();
+        if !is_request {
+            return reply;
+        }
+
+        let mut lines: Vec<&str> = reply.split('\r').collect();
+        let echo = lines.first().is_some_and(|l| !l.is_empty() && l.trim() == command.trim());
+        let body_start = if echo { 1 } else { 0 };
+
+        if rng.gen_bool(self.no_data.clamp(0.0, 1.0)) {
+            let mut distorted: Vec<&str> = lines[..body_start].to_vec();
+            distorted.extend(["NO DATA", "", ">"]);
+            return distorted.join("\r");
+        }
+
+        if self.drop_frames > 0.0 {
+            let drop_frames = self.drop_frames.clamp(0.0, 1.0);
+            lines = lines
+                .into_iter()
+                .enumerate()
+                .filter(|(i, l)| *i < body_start || !is_consecutive_frame(l) || !rng.gen_bool(drop_frames))
+                .map(|(_, l)| l)
+                .collect();
+        }
+
+        lines.join("\r")
+    }
+}
+
+fn is_consecutive_frame(line: &str) -> bool {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        // headers off: "1: 00 00 ..."
+        Some(index) if index.ends_with(':') => index != "0:",
+        // headers on: "7EC 21 00 00 ..."
+        Some(_header) => parts.next().is_some_and(|pci| pci.len() == 2 && pci.starts_with('2')),
+        None => false,
+    }
+}
+
+fn serve(mut stream: TcpStream, pack: Arc<Mutex<PackState>>, simulation: Arc<Mutex<Simulation>>) -> std::io::Result<()> {
+    let mut emulator = Emulator::new(pack);
+    let mut line = Vec::new();
+    let mut buf = [0u8; 256];
+    // wake up regularly to send broadcasts while monitoring
+    stream.set_read_timeout(Some(MONITOR_INTERVAL))?;
+    loop {
+        let len = match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if emulator.is_monitoring() {
+                    stream.write_all(emulator.monitor_frames().as_bytes())?;
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for b in &buf[..len] {
+            if emulator.is_monitoring() {
+                stream.write_all(emulator.stop_monitoring().as_bytes())?;
+                continue;
+            }
+            match b {
+                b'\r' => {
+                    let command = String::from_utf8_lossy(&line).to_string();
+                    line.clear();
+                    let reply = emulator.handle_command(&command);
+                    let reply = simulation.lock().unwrap().distort(&command, reply);
+                    stream.write_all(reply.as_bytes())?;
+                }
+                b'\n' => {}
+                _ => line.push(*b),
+            }
+        }
+    }
+}
+
+fn load_script(path: &str) -> Result<Vec<(Duration, String)>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    content
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let (at, scenario) = l.split_once(char::is_whitespace).ok_or(format!("bad script line: {}", l))?;
+            let at = at.parse::<f64>().map_err(|e| format!("bad time in {}: {}", l, e))?;
+            Ok((Duration::from_secs_f64(at), scenario.trim().to_string()))
+        })
+        .collect()
+}
+
+fn main() -> Result<(), String> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut listen = "127.0.0.1:50059".to_string();
+    let mut simulation = Simulation::default();
+    let mut script = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().ok_or("--listen requires an address")?,
+            "--scenario" => simulation.apply(&args.next().ok_or("--scenario requires a name")?)?,
+            "--script" => script = load_script(&args.next().ok_or("--script requires a file")?)?,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    let pack = Arc::new(Mutex::new(PackState::default()));
+    let simulation = Arc::new(Mutex::new(simulation));
+
+    {
+        let pack = pack.clone();
+        let simulation = simulation.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            let mut script = script.into_iter().peekable();
+            loop {
+                while let Some((_, scenario)) = script.next_if(|(at, _)| *at <= start.elapsed()) {
+                    if let Err(e) = simulation.lock().unwrap().apply(&scenario) {
+                        warn!("Script: {}", e);
+                    }
+                }
+                simulation.lock().unwrap().tick(&mut pack.lock().unwrap(), TICK);
+                thread::sleep(TICK);
+            }
+        });
+    }
+
+    let listener = TcpListener::bind(&listen).map_err(|e| format!("can't listen on {}: {}", listen, e))?;
+    info!("ELM327 emulator listening on {}", listen);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        info!("Client {} connected", peer);
+        let pack = pack.clone();
+        let simulation = simulation.clone();
+        thread::spawn(move || {
+            match serve(stream, pack, simulation) {
+                Ok(()) => info!("Client {} disconnected", peer),
+                Err(e) => warn!("Client {} dropped: {}", peer, e),
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operating_time_counts_short_ticks() {
+        let mut simulation = Simulation::default();
+        let mut pack = PackState::default();
+        let start = pack.operating_time;
+        for _ in 0..50 {
+            simulation.tick(&mut pack, Duration::from_millis(200));
+        }
+        assert_eq!(pack.operating_time - start, 10);
+    }
+}
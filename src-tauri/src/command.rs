@@ -1,15 +1,71 @@
-use std::sync;
-use log::debug;
-use tauri::State;
+use std::path::PathBuf;
+use std::sync::{self, mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use rand::Rng;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
 use crate::{AppState, elm327, kia};
 use crate::elm327::Elm327;
+use crate::elm327::capabilities::AdapterCapabilities;
+use crate::elm327::capture::{CaptureRecord, CaptureSettings, NoCapture, SessionMetadata};
+use crate::elm327::monitor::MonitorSettings;
+use crate::obd2::dtc::Dtc;
+use crate::obd2::mode01::PidValue;
+use crate::kia::{LowVoltageSettings, VehicleIdentity};
+use crate::kia::profile::ProfileInfo;
 use crate::error::CommandError;
+use crate::scheduler::{PollingSettings, Scheduler};
+use crate::worker::{Priority, Worker};
 
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    device_name: String,
+    capabilities: AdapterCapabilities,
+    vehicle: VehicleIdentity,
+    profile: ProfileInfo,
+}
+
+/// `profile` is the id of one of `list_vehicle_profiles`
 #[tauri::command]
-pub async fn connect(connection_method: &str, connection_param: &str, app_state: State<'_, sync::Mutex<AppState>>) -> Result<String, CommandError> {
+pub async fn connect(connection_method: &str, connection_param: &str, profile: &str, app_handle: AppHandle, app_state: State<'_, sync::Mutex<AppState>>) -> Result<ConnectionInfo, CommandError> {
+    let profile = kia::profile::profile(profile).ok_or_else(|| CommandError {
+        code: "unknown_vehicle_profile".to_string(),
+        message: "Unknown vehicle profile".to_string(),
+        parameters: Some(vec!(profile.to_string())),
+    })?;
+    let signals = kia::signals::SignalSet::load(profile.as_ref(), signal_directory(&app_handle).as_deref()).map_err(|e| {
+        warn!("Can't load signal definitions: {:?}", e);
+        e
+    })?;
+    let profile_info = ProfileInfo::new(profile.as_ref(), &signals);
+
+    // the old connection has to let go of the port before it is opened again. Jobs on the worker
+    // may lock the app state, it can't be held while waiting for them.
+    let (worker, monitor) = {
+        let mut app_state = app_state.lock().unwrap();
+        (app_state.worker.take(), app_state.can_monitor.take())
+    };
+    if let Some(monitor) = monitor {
+        monitor.stop.store(true, Ordering::Relaxed);
+    }
+    if let Some(worker) = worker {
+        worker.close();
+    }
+
     let transport: Box<dyn elm327::transport::Transport> = match connection_method {
         "wifi" => Box::new(elm327::transport::WiFi::new(connection_param)?),
         "serial" => Box::new(elm327::transport::Serial::new(connection_param)?),
+        // the simulated BMS answers in the Soul EV's layout, other profiles would decode nonsense
+        "demo" if profile.id() != kia::simulator::Simulator::PROFILE => return Err(CommandError {
+            code: "demo_profile_unsupported".to_string(),
+            message: "The demo only simulates a Kia Soul EV".to_string(),
+            parameters: Some(vec!(profile.name().to_string())),
+        }),
+        "demo" => Box::new(kia::simulator::Simulator::demo()),
+        "replay" => Box::new(elm327::transport::Replay::from_file(connection_param, elm327::transport::ReplayMode::Lenient)?),
+        "replay_strict" => Box::new(elm327::transport::Replay::from_file(connection_param, elm327::transport::ReplayMode::Strict)?),
         _ => return Err(CommandError {
             code: "invalid_connection_method".to_string(),
             message: "Invalid connection method".to_string(),
@@ -17,34 +73,255 @@ pub async fn connect(connection_method: &str, connection_param: &str, app_state:
         })
     };
 
-    let elm327 = Elm327::new(transport).map_err(|e| {
-        match e.current_context() {
-            elm327::Error::Communication | elm327::Error::Other => CommandError::new_internal(),
-            elm327::Error::NotConnected => CommandError::new_not_connected(),
+    // lets us reproduce flaky adapter behaviour on a good connection
+    let transport: Box<dyn elm327::transport::Transport> = match std::env::var("SOULBATSTAT_FAULTS") {
+        Ok(spec) => Box::new(elm327::transport::FaultInjector::new(transport, elm327::transport::FaultConfig::parse(&spec)?)),
+        Err(_) => transport,
+    };
+
+    let capture_settings = app_state.lock().unwrap().capture_settings.clone();
+    let capture_directory = app_handle.path_resolver().app_log_dir().map(|dir| dir.join("captures"));
+    let mut capture = match capture_settings.open_sink(capture_directory) {
+        Ok(capture) => capture,
+        Err(e) => {
+            warn!("Can't open capture, connecting without it: {:?}", e);
+            Box::new(NoCapture)
         }
-    })?;
-    let connected_device_name = elm327.get_connected_device_name();
-    let mut kia = kia::Kia::new(elm327);
+    };
+    capture.record(&CaptureRecord::Session(SessionMetadata::new(connection_method, profile.id())));
+
+    let elm327 = Elm327::new(transport, capture)?;
+    let capabilities = elm327.get_capabilities().clone();
+    let connected_device_name = match elm327.get_transport_description() {
+        Some(description) => format!("{} ({})", elm327.get_connected_device_name(), description),
+        None => elm327.get_connected_device_name(),
+    };
+    let mut kia = kia::Kia::new(elm327, profile, signals);
+    {
+        let app_state = app_state.lock().unwrap();
+        kia.set_expert_mode(app_state.expert_mode);
+        kia.set_low_voltage_settings(app_state.low_voltage_settings);
+    }
     kia.init()?;
-    app_state.lock().unwrap().kia.replace(kia);
-    Ok(connected_device_name)
+    let vehicle = kia.get_identity()?;
+    app_state.lock().unwrap().worker.replace(Worker::spawn(kia));
+    Ok(ConnectionInfo {
+        device_name: connected_device_name,
+        capabilities,
+        vehicle,
+        profile: profile_info,
+    })
+}
+
+#[tauri::command]
+pub fn list_vehicle_profiles(app_handle: AppHandle) -> Vec<ProfileInfo> {
+    let directory = signal_directory(&app_handle);
+    kia::profile::profiles()
+        .iter()
+        .map(|profile| {
+            // connect reports the broken file, until then the built-in definitions are counted
+            let signals = kia::signals::SignalSet::load(profile.as_ref(), directory.as_deref())
+                .or_else(|_| kia::signals::SignalSet::parse(profile.id(), profile.signals()))
+                .expect("built-in signal definitions are valid");
+            ProfileInfo::new(profile.as_ref(), &signals)
+        })
+        .collect()
+}
+
+/// Definitions put there replace the built-in ones of their profile
+fn signal_directory(app_handle: &AppHandle) -> Option<PathBuf> {
+    app_handle.path_resolver().app_config_dir().map(|dir| dir.join("signals"))
 }
 
 
+/// Closes the connection right away, the request in progress is cancelled
 #[tauri::command]
 pub fn disconnect(app_state: State<'_, sync::Mutex<AppState>>) {
-    app_state.lock().unwrap().kia = None;
+    let mut app_state = app_state.lock().unwrap();
+    app_state.worker = None;
+    if let Some(monitor) = app_state.can_monitor.take() {
+        monitor.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs `f` on the connection's worker and waits for it without holding the app state
+async fn run_on_worker<T: Send + 'static>(
+    app_state: &State<'_, sync::Mutex<AppState>>,
+    priority: Priority,
+    f: impl FnOnce(&mut kia::Kia) -> T + Send + 'static,
+) -> Result<T, CommandError> {
+    let reply = {
+        let app_state = app_state.lock().unwrap();
+        // the monitor keeps the worker busy until it is stopped
+        if app_state.can_monitor.is_some() {
+            return Err(CommandError::new_can_monitor_running());
+        }
+        let worker = app_state.worker.as_ref().ok_or_else(CommandError::new_not_connected)?;
+        worker.submit(priority, f)
+    };
+    wait_for(reply).await
+}
+
+async fn wait_for<T: Send + 'static>(reply: mpsc::Receiver<T>) -> Result<T, CommandError> {
+    tauri::async_runtime::spawn_blocking(move || reply.recv())
+        .await
+        .map_err(|_| CommandError::new_internal())?
+        // the worker was closed before it got to the job
+        .map_err(|_| CommandError::new_not_connected())
+}
+
+pub struct CanMonitor {
+    stop: Arc<AtomicBool>,
+}
+
+/// Streams raw CAN frames in `can_frames` events until `stop_can_monitor`. Nothing else can be
+/// read from the car in the meantime.
+#[tauri::command]
+pub async fn start_can_monitor(settings: MonitorSettings, app_handle: AppHandle, app_state: State<'_, sync::Mutex<AppState>>) -> Result<(), CommandError> {
+    let mut app_state = app_state.lock().unwrap();
+    if app_state.can_monitor.is_some() {
+        return Err(CommandError::new_can_monitor_running());
+    }
+    let worker = app_state.worker.as_ref().ok_or_else(CommandError::new_not_connected)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        worker.submit(Priority::High, move |kia| {
+            let result = kia.monitor_can(&settings, &stop, |frames| {
+                if let Err(e) = app_handle.emit_all("can_frames", frames) {
+                    warn!("Can't emit CAN frames: {}", e);
+                }
+            });
+            let error = result.err().map(|e| {
+                warn!("CAN monitor failed: {:?}", e);
+                CommandError::from(e)
+            });
+            // a failed monitor gives the connection back by itself
+            let app_state = app_handle.state::<sync::Mutex<AppState>>();
+            let mut app_state = app_state.lock().unwrap();
+            if app_state.can_monitor.as_ref().is_some_and(|monitor| Arc::ptr_eq(&monitor.stop, &stop)) {
+                app_state.can_monitor = None;
+            }
+            let _ = app_handle.emit_all("can_monitor_stopped", error);
+        });
+    }
+    app_state.can_monitor = Some(CanMonitor { stop });
+
+    Ok(())
+}
+
+/// Returns once the connection is set up for reading car info again
+#[tauri::command]
+pub async fn stop_can_monitor(app_state: State<'_, sync::Mutex<AppState>>) -> Result<(), CommandError> {
+    let Some(monitor) = app_state.lock().unwrap().can_monitor.take() else {
+        return Ok(());
+    };
+    monitor.stop.store(true, Ordering::Relaxed);
+    run_on_worker(&app_state, Priority::High, |_| ()).await
+}
+
+#[tauri::command]
+pub fn get_capture_settings(app_state: State<'_, sync::Mutex<AppState>>) -> CaptureSettings {
+    app_state.lock().unwrap().capture_settings.clone()
+}
+
+/// Applies to the next connection
+#[tauri::command]
+pub fn set_capture_settings(capture_settings: CaptureSettings, app_state: State<'_, sync::Mutex<AppState>>) {
+    app_state.lock().unwrap().capture_settings = capture_settings;
+}
+
+#[tauri::command]
+pub fn get_low_voltage_settings(app_state: State<'_, sync::Mutex<AppState>>) -> LowVoltageSettings {
+    app_state.lock().unwrap().low_voltage_settings
+}
+
+#[tauri::command]
+pub fn set_low_voltage_settings(settings: LowVoltageSettings, app_state: State<'_, sync::Mutex<AppState>>) {
+    let mut app_state = app_state.lock().unwrap();
+    app_state.low_voltage_settings = settings;
+    if let Some(worker) = app_state.worker.as_ref() {
+        worker.submit(Priority::High, move |kia| kia.set_low_voltage_settings(settings));
+    }
+}
+
+#[tauri::command]
+pub fn get_expert_mode(app_state: State<'_, sync::Mutex<AppState>>) -> bool {
+    app_state.lock().unwrap().expert_mode
+}
+
+/// Lets requests through that write to the car, reset or unlock ECUs. Fault codes can be cleared
+/// without it, they have their own confirmation.
+#[tauri::command]
+pub fn set_expert_mode(enabled: bool, app_state: State<'_, sync::Mutex<AppState>>) {
+    let mut app_state = app_state.lock().unwrap();
+    app_state.expert_mode = enabled;
+    if let Some(worker) = app_state.worker.as_ref() {
+        worker.submit(Priority::High, move |kia| kia.set_expert_mode(enabled));
+    }
 }
 
+/// Starts reading the car in the background, see `Scheduler`. Carries on across reconnects until
+/// `stop_polling`.
+#[tauri::command]
+pub fn start_polling(app_handle: AppHandle, app_state: State<'_, sync::Mutex<AppState>>) {
+    let mut app_state = app_state.lock().unwrap();
+    if app_state.scheduler.is_none() {
+        let settings = app_state.polling_settings.clone();
+        app_state.scheduler = Some(Scheduler::spawn(settings, app_handle));
+    }
+}
 
+/// Reads already queued still finish, their samples aren't emitted
+#[tauri::command]
+pub fn stop_polling(app_state: State<'_, sync::Mutex<AppState>>) {
+    app_state.lock().unwrap().scheduler = None;
+}
 
 #[tauri::command]
-pub async fn get_car_info(app_state: State<'_, sync::Mutex<AppState>>) -> Result<kia::CarInfo, CommandError> {
-    if let Some(kia) = app_state.lock().unwrap().kia.as_mut() {
-        return Ok(kia.get_car_info()?);
+pub fn get_polling_settings(app_state: State<'_, sync::Mutex<AppState>>) -> PollingSettings {
+    app_state.lock().unwrap().polling_settings.clone()
+}
+
+#[tauri::command]
+pub fn set_polling_settings(settings: PollingSettings, app_state: State<'_, sync::Mutex<AppState>>) {
+    let mut app_state = app_state.lock().unwrap();
+    if let Some(scheduler) = app_state.scheduler.as_ref() {
+        scheduler.set_settings(settings.clone());
     }
+    app_state.polling_settings = settings;
+}
 
-    Err(CommandError::new_not_connected())
+#[tauri::command]
+pub async fn get_dtcs(app_state: State<'_, sync::Mutex<AppState>>) -> Result<Vec<Dtc>, CommandError> {
+    run_on_worker(&app_state, Priority::High, |kia| kia.get_dtcs().map_err(CommandError::from)).await?
+}
+
+/// First step of clearing the fault codes, the token has to be passed to `clear_dtcs` within a
+/// minute so codes are never cleared by a stray call
+#[tauri::command]
+pub fn request_dtc_clear_token(app_state: State<'_, sync::Mutex<AppState>>) -> String {
+    let token = format!("{:016x}", rand::thread_rng().gen::<u64>());
+    app_state.lock().unwrap().dtc_clear_token = Some((token.clone(), Instant::now()));
+    token
+}
+
+#[tauri::command]
+pub async fn clear_dtcs(token: String, app_state: State<'_, sync::Mutex<AppState>>) -> Result<(), CommandError> {
+    const TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+    match app_state.lock().unwrap().dtc_clear_token.take() {
+        Some((expected, issued)) if expected == token && issued.elapsed() < TOKEN_LIFETIME => {}
+        _ => return Err(CommandError::new_invalid_confirmation_token()),
+    }
+    run_on_worker(&app_state, Priority::High, |kia| kia.clear_dtcs().map_err(CommandError::from)).await?
+}
+
+/// Standard OBD-II values the car supports
+#[tauri::command]
+pub async fn get_obd2_values(app_state: State<'_, sync::Mutex<AppState>>) -> Result<Vec<PidValue>, CommandError> {
+    run_on_worker(&app_state, Priority::High, |kia| kia.get_obd2_values().map_err(CommandError::from)).await?
 }
 
 #[tauri::command]
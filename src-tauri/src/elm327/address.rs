@@ -0,0 +1,125 @@
+/// Request and response CAN IDs of an ECU, or of the OBD-II broadcast. See `Elm327::set_address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EcuAddress {
+    pub request_id: u32,
+    /// `None` lets answers from every ECU through
+    pub response_id: Option<u32>,
+    /// 29-bit IDs, they only work with a 29-bit protocol selected
+    pub extended: bool,
+}
+
+impl EcuAddress {
+    /// OBD-II functional requests, answered by every emissions related ECU
+    pub const OBD_BROADCAST: Self = Self { request_id: 0x7DF, response_id: None, extended: false };
+
+    pub const fn standard(request_id: u32, response_id: u32) -> Self {
+        Self { request_id, response_id: Some(response_id), extended: false }
+    }
+
+    fn format_id(&self, id: u32) -> String {
+        if self.extended {
+            format!("{:08X}", id)
+        } else {
+            format!("{:03X}", id)
+        }
+    }
+
+    /// AT commands that point the adapter at this address. Without a receive address filter
+    /// (`AT CRA`) the answers are picked out with a CAN filter and mask.
+    pub(super) fn commands(&self, receive_address_filter: bool) -> Vec<String> {
+        let mut commands = Vec::new();
+        if self.extended {
+            // the top 5 bits of a 29-bit ID are the priority, set apart from the header
+            commands.push(format!("AT CP {:02X}", self.request_id >> 24));
+            commands.push(format!("AT SH {:06X}", self.request_id & 0xFF_FFFF));
+        } else {
+            commands.push(format!("AT SH {:03X}", self.request_id));
+        }
+
+        let full_mask = if self.extended { 0x1FFF_FFFF } else { 0x7FF };
+        match (self.response_id, receive_address_filter) {
+            (Some(response_id), true) => commands.push(format!("AT CRA {}", self.format_id(response_id))),
+            (Some(response_id), false) => {
+                commands.push(format!("AT CF {}", self.format_id(response_id)));
+                commands.push(format!("AT CM {}", self.format_id(full_mask)));
+            }
+            (None, true) => commands.push("AT CRA".to_string()),
+            // diagnostic responses all come from 7E8-7EF, or 18DAF1xx with 29-bit IDs
+            (None, false) if self.extended => {
+                commands.push("AT CF 18DAF100".to_string());
+                commands.push("AT CM 1FFFFF00".to_string());
+            }
+            (None, false) => {
+                commands.push("AT CF 7E8".to_string());
+                commands.push("AT CM 7F0".to_string());
+            }
+        }
+
+        match self.response_id {
+            // flow control frames go to the ECU we talk to, the automatic ones only suit 7E0-7E7
+            Some(_) => {
+                commands.push(format!("AT FC SH {}", self.format_id(self.request_id)));
+                commands.push("AT FC SD 30 00 00".to_string());
+                commands.push("AT FC SM 1".to_string());
+            }
+            None => commands.push("AT FC SM 0".to_string()),
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error_stack::ResultExt;
+    use crate::elm327::capture::NoCapture;
+    use crate::elm327::isotp::{self, Payload};
+    use crate::elm327::{Command, Elm327, Result};
+    use crate::kia::simulator::{PackState, Simulator};
+
+    struct Responses(&'static str);
+
+    impl Command for Responses {
+        type Response = Vec<Payload>;
+
+        fn serial_command(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn parse_result(&self, response: String) -> Result<Self::Response> {
+            isotp::reassemble(&response).change_context(crate::elm327::Error::Other)
+        }
+    }
+
+    const BMS: EcuAddress = EcuAddress { request_id: 0x18DAE4F1, response_id: Some(0x18DAF1E4), extended: true };
+
+    fn elm327(spaces: bool) -> Elm327 {
+        let mut elm327 = Elm327::new(Box::new(Simulator::new(PackState::default())), Box::new(NoCapture)).unwrap();
+        elm327.serial_cmd("AT H1").unwrap();
+        elm327.serial_cmd(if spaces { "AT S1" } else { "AT S0" }).unwrap();
+        elm327
+    }
+
+    #[test]
+    fn extended_round_trip() {
+        for spaces in [true, false] {
+            let mut elm327 = elm327(spaces);
+            // multi-frame, the part number doesn't fit into one frame
+            let payloads = elm327.execute_command_on(&BMS, Responses("22 F1 87")).unwrap();
+            let expected = [&[0x62, 0xF1, 0x87], "37501-E4000".as_bytes()].concat();
+            assert_eq!(payloads, vec![Payload { ecu: Some(0x18DAF1E4), data: expected }]);
+
+            let payloads = elm327.execute_command_on(&BMS, Responses("3E 00")).unwrap();
+            assert_eq!(payloads, vec![Payload { ecu: Some(0x18DAF1E4), data: vec![0x7E, 0x00] }]);
+        }
+    }
+
+    #[test]
+    fn extended_broadcast() {
+        let mut elm327 = elm327(true);
+        let broadcast = EcuAddress { request_id: 0x18DB33F1, response_id: None, extended: true };
+        let payloads = elm327.execute_command_on(&broadcast, Responses("01 00")).unwrap();
+        let ecus: Vec<Option<u32>> = payloads.iter().map(|payload| payload.ecu).collect();
+        assert!(ecus.contains(&Some(0x18DAF1E2)), "{:?}", ecus);
+    }
+}
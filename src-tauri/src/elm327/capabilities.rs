@@ -0,0 +1,169 @@
+use log::{debug, warn};
+use serde::Serialize;
+
+use super::{Elm327, Error, Result};
+
+/// What the adapter answered to the identification and probe commands sent on connect
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AdapterCapabilities {
+    /// `ATI`, e.g. `ELM327 v1.5`
+    pub identifier: String,
+    /// Version number from the identifier, e.g. `1.5`
+    pub version: Option<String>,
+    /// `AT@1`
+    pub description: Option<String>,
+    /// `STI`, only answered by STN chips
+    pub stn_identifier: Option<String>,
+    /// `STDI`
+    pub stn_device: Option<String>,
+    /// `AT CRA`, receive address filter
+    pub receive_address_filter: bool,
+    /// `AT CAF`, CAN auto formatting of ISO-TP frames
+    pub can_auto_format: bool,
+    /// `AT AL`, messages longer than 7 bytes
+    pub long_messages: bool,
+    /// `AT MA`, monitoring all bus traffic
+    pub monitor_all: bool,
+    pub likely_clone: bool,
+}
+
+impl AdapterCapabilities {
+    pub fn probe(device: &mut Elm327) -> Result<Self> {
+        let mut capabilities = AdapterCapabilities {
+            identifier: device.serial_cmd("AT I")?.trim().to_string(),
+            description: Self::query(device, "AT @1")?,
+            stn_identifier: Self::query(device, "STI")?,
+            stn_device: Self::query(device, "STDI")?,
+            receive_address_filter: Self::supports(device, "AT CRA")?,
+            can_auto_format: Self::supports(device, "AT CAF1")?,
+            long_messages: Self::supports(device, "AT AL")?,
+            // any byte sent after the command stops the monitor, the line end does it right away
+            monitor_all: Self::supports(device, "AT MA")?,
+            ..Default::default()
+        };
+        capabilities.version = capabilities.identifier
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix('v').or_else(|| part.strip_prefix('V')))
+            .map(|version| version.to_string());
+        capabilities.likely_clone = capabilities.detect_clone();
+        debug!("Adapter capabilities: {:?}", capabilities);
+
+        Ok(capabilities)
+    }
+
+    /// Name to show for the adapter, STN chips report a more specific one
+    pub fn name(&self) -> String {
+        self.stn_device.clone().unwrap_or_else(|| self.identifier.clone())
+    }
+
+    fn query(device: &mut Elm327, command: &str) -> Result<Option<String>> {
+        Ok(match Self::run(device, command)? {
+            Some(response) => Some(response.trim().to_string()).filter(|r| !r.is_empty()),
+            None => None,
+        })
+    }
+
+    fn supports(device: &mut Elm327, command: &str) -> Result<bool> {
+        Ok(Self::run(device, command)?.is_some())
+    }
+
+    /// Sends a command that the adapter may not know, `None` when it doesn't
+    fn run(device: &mut Elm327, command: &str) -> Result<Option<String>> {
+        match device.serial_cmd(command) {
+            Ok(response) => Ok(Some(response)),
+            Err(e) if *e.current_context() == Error::UnknownCommand => Ok(None),
+            // STOPPED, NO DATA and the like still mean the command was understood
+            Err(e) if e.current_context().is_response_error() => Ok(Some(String::new())),
+            Err(e) if *e.current_context() == Error::Communication => {
+                // some clones never finish a command they don't implement
+                warn!("No answer to {}, treating it as unsupported", command);
+                // an empty line interrupts whatever the adapter is still doing
+                device.send_serial_str("")?;
+                device.flush()?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn detect_clone(&self) -> bool {
+        if self.stn_identifier.is_some() {
+            return false;
+        }
+        let version = self.version
+            .as_deref()
+            .and_then(|v| v.split_once('.'))
+            .and_then(|(major, minor)| Some((major.parse::<u32>().ok()?, minor.parse::<u32>().ok()?)));
+        match version {
+            // every genuine chip since v1.4 implements all the probed commands, the version string alone
+            // proves nothing since clones copy it
+            Some(version) if version >= (1, 4) => {
+                !(self.receive_address_filter && self.can_auto_format && self.long_messages && self.monitor_all)
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chip reporting `identifier` that answers every probe
+    fn full(identifier: &str) -> AdapterCapabilities {
+        AdapterCapabilities {
+            identifier: identifier.to_string(),
+            version: identifier.split_whitespace().find_map(|part| part.strip_prefix('v')).map(|v| v.to_string()),
+            receive_address_filter: true,
+            can_auto_format: true,
+            long_messages: true,
+            monitor_all: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn genuine_v15() {
+        assert!(!full("ELM327 v1.5").detect_clone());
+    }
+
+    #[test]
+    fn stn_chip() {
+        let capabilities = AdapterCapabilities {
+            stn_identifier: Some("STN1110 v4.3.0".to_string()),
+            stn_device: Some("OBDLink SX r4.2".to_string()),
+            ..full("ELM327 v1.5")
+        };
+        assert!(!capabilities.detect_clone());
+
+        // an STN chip is no clone whatever it claims to be
+        let capabilities = AdapterCapabilities { monitor_all: false, ..capabilities };
+        assert!(!capabilities.detect_clone());
+    }
+
+    #[test]
+    fn clone_missing_commands() {
+        // `?` to AT MA, the usual cut of the cheap v1.5 clones
+        let capabilities = AdapterCapabilities { monitor_all: false, ..full("ELM327 v1.5") };
+        assert!(capabilities.detect_clone());
+
+        let capabilities = AdapterCapabilities { receive_address_filter: false, ..full("ELM327 v1.5") };
+        assert!(capabilities.detect_clone());
+
+        let capabilities = AdapterCapabilities { long_messages: false, ..full("ELM327 v2.1") };
+        assert!(capabilities.detect_clone());
+    }
+
+    #[test]
+    fn older_versions() {
+        // AT MA and the rest only came with later versions, missing them is expected
+        let capabilities = AdapterCapabilities { monitor_all: false, can_auto_format: false, ..full("ELM327 v1.3") };
+        assert!(!capabilities.detect_clone());
+    }
+
+    #[test]
+    fn unknown_version() {
+        assert!(full("OBDII to RS232 Interpreter").detect_clone());
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use error_stack::{Report, ResultExt};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Result};
+
+const CAPTURE_PREFIX: &str = "capture-";
+const CAPTURE_EXTENSION: &str = "jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Write,
+    Read,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionMetadata {
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub app_version: String,
+    pub vehicle_profile: String,
+    pub connection_method: String,
+}
+
+impl SessionMetadata {
+    pub fn new(connection_method: &str, vehicle_profile: &str) -> Self {
+        Self {
+            started_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            vehicle_profile: vehicle_profile.to_string(),
+            connection_method: connection_method.to_string(),
+        }
+    }
+}
+
+/// One line of a capture file. `t` is the monotonic time since the session started, in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptureRecord {
+    Session(SessionMetadata),
+    Adapter { t: f64, name: String, transport: Option<String> },
+    Data { t: f64, direction: Direction, hex: String, text: String },
+    /// A request the read-only guard kept from being sent
+    Rejected { t: f64, command: String },
+}
+
+impl CaptureRecord {
+    pub fn data(elapsed: Duration, direction: Direction, data: &[u8]) -> Self {
+        CaptureRecord::Data {
+            t: elapsed.as_secs_f64(),
+            direction,
+            hex: data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+            text: String::from_utf8_lossy(data).to_string(),
+        }
+    }
+
+    /// Raw bytes of a data record
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            CaptureRecord::Data { hex, .. } => hex
+                .split_whitespace()
+                .map(|b| u8::from_str_radix(b, 16).ok())
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+pub trait CaptureSink: Send {
+    fn record(&mut self, record: &CaptureRecord);
+}
+
+/// Drops everything
+pub struct NoCapture;
+
+impl CaptureSink for NoCapture {
+    fn record(&mut self, _record: &CaptureRecord) {}
+}
+
+/// Keeps records in memory, clones share the same records
+#[derive(Clone, Default)]
+pub struct MemoryCapture(Arc<Mutex<Vec<CaptureRecord>>>);
+
+impl MemoryCapture {
+    pub fn records(&self) -> Vec<CaptureRecord> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl CaptureSink for MemoryCapture {
+    fn record(&mut self, record: &CaptureRecord) {
+        self.0.lock().unwrap().push(record.clone());
+    }
+}
+
+/// Writes JSON lines into `capture-<unix millis>.jsonl` files in a directory, keeping at most
+/// `max_files` of them. A session that outgrows `max_file_size` continues in a new file.
+pub struct FileCapture {
+    directory: PathBuf,
+    max_files: usize,
+    max_file_size: u64,
+    file: fs::File,
+    written: u64,
+    session: Option<SessionMetadata>,
+    failed: bool,
+}
+
+impl FileCapture {
+    pub fn create(directory: impl AsRef<Path>, max_files: usize, max_file_size: u64) -> Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let file = Self::open_next(&directory, max_files)?;
+
+        Ok(Self {
+            directory,
+            max_files,
+            max_file_size,
+            file,
+            written: 0,
+            session: None,
+            failed: false,
+        })
+    }
+
+    fn open_next(directory: &Path, max_files: usize) -> Result<fs::File> {
+        fs::create_dir_all(directory)
+            .change_context(Error::Other)
+            .attach_printable(format!("Can't create capture directory {}", directory.display()))?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let path = directory.join(format!("{}{}.{}", CAPTURE_PREFIX, now, CAPTURE_EXTENSION));
+        let file = fs::File::create(&path)
+            .change_context(Error::Other)
+            .attach_printable(format!("Can't create capture file {}", path.display()))?;
+
+        Self::rotate(directory, max_files);
+        Ok(file)
+    }
+
+    /// Removes the oldest captures so at most `max_files` remain
+    fn rotate(directory: &Path, max_files: usize) {
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        let mut captures: Vec<(u128, PathBuf)> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                let stamp = name.strip_prefix(CAPTURE_PREFIX)?.strip_suffix(&format!(".{}", CAPTURE_EXTENSION))?;
+                Some((stamp.parse().ok()?, e.path()))
+            })
+            .collect();
+        captures.sort();
+
+        let excess = captures.len().saturating_sub(max_files.max(1));
+        for (_, path) in captures.into_iter().take(excess) {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Can't remove old capture {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn write_line(&mut self, record: &CaptureRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl CaptureSink for FileCapture {
+    fn record(&mut self, record: &CaptureRecord) {
+        if let CaptureRecord::Session(session) = record {
+            self.session = Some(session.clone());
+        }
+
+        if self.written >= self.max_file_size {
+            match Self::open_next(&self.directory, self.max_files) {
+                Ok(file) => {
+                    self.file = file;
+                    self.written = 0;
+                    // every file starts with the session metadata so it can be replayed on its own
+                    if let Some(session) = self.session.clone() {
+                        let _ = self.write_line(&CaptureRecord::Session(session));
+                    }
+                }
+                Err(e) => warn!("Can't continue capture in a new file: {:?}", e),
+            }
+        }
+
+        if let Err(e) = self.write_line(record) {
+            // warn once, the capture must never break the connection
+            if !self.failed {
+                warn!("Can't write capture: {}", e);
+                self.failed = true;
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureSettings {
+    pub enabled: bool,
+    /// Defaults to the app's log directory
+    pub directory: Option<String>,
+    pub max_files: usize,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: None,
+            max_files: 20,
+        }
+    }
+}
+
+impl CaptureSettings {
+    const MAX_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
+    pub fn open_sink(&self, default_directory: Option<PathBuf>) -> Result<Box<dyn CaptureSink>> {
+        if !self.enabled {
+            return Ok(Box::new(NoCapture));
+        }
+
+        let directory = self.directory
+            .as_ref()
+            .map(PathBuf::from)
+            .or(default_directory)
+            .ok_or_else(|| Report::new(Error::Other).attach_printable("No capture directory available"))?;
+
+        Ok(Box::new(FileCapture::create(directory, self.max_files, Self::MAX_FILE_SIZE)?))
+    }
+}
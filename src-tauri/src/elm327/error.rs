@@ -1,12 +1,40 @@
-
 pub type Result<T> = error_stack::Result<T, Error>;
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
 pub enum Error {
     #[error("Communication error")]
     Communication,
     #[error("Not connected")]
     NotConnected,
+    #[error("No data")]
+    NoData,
+    #[error("CAN error")]
+    Can,
+    #[error("Buffer full")]
+    BufferFull,
+    #[error("Bus init error")]
+    BusInit,
+    #[error("Unknown command")]
+    UnknownCommand,
+    #[error("Stopped")]
+    Stopped,
+    #[error("Unable to connect")]
+    UnableToConnect,
+    #[error("Still searching for a protocol")]
+    Searching,
+    #[error("Request rejected by the read-only guard")]
+    RequestRejected,
+    /// Strict replay got a command the capture doesn't have next, the transport error below it
+    /// tells which
+    #[error("Command doesn't match the replayed capture")]
+    ReplayMismatch,
     #[error("Other error")]
     Other,
 }
+
+impl Error {
+    /// Errors the adapter reported in its response, as opposed to failures to talk to the adapter
+    pub fn is_response_error(&self) -> bool {
+        !matches!(self, Error::Communication | Error::NotConnected | Error::RequestRejected | Error::ReplayMismatch | Error::Other)
+    }
+}
@@ -0,0 +1,98 @@
+// Keeps requests that change the state of the car from reaching it by accident. Every request
+// that isn't an AT command is classified by its service ID, the ones that write, reset, run
+// routines or unlock an ECU only go out with write access granted.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestClass {
+    /// AT commands and services that only read
+    Read,
+    Write,
+}
+
+/// Services that change something in an ECU, OBD-II, KWP2000 and UDS share the numbering
+const WRITE_SERVICES: [u8; 21] = [
+    0x04, // OBD clear DTCs
+    0x08, // OBD control of on-board system
+    0x11, // ECUReset
+    0x14, // ClearDiagnosticInformation
+    0x27, // SecurityAccess
+    0x28, // CommunicationControl
+    0x2C, // DynamicallyDefineDataIdentifier
+    0x2E, // WriteDataByIdentifier
+    0x2F, // InputOutputControlByIdentifier
+    0x30, // KWP InputOutputControlByLocalIdentifier
+    0x31, // RoutineControl, KWP StartRoutineByLocalIdentifier
+    0x32, // KWP StopRoutineByLocalIdentifier
+    0x34, // RequestDownload
+    0x35, // RequestUpload
+    0x36, // TransferData
+    0x37, // RequestTransferExit
+    0x38, // RequestFileTransfer
+    0x3B, // KWP WriteDataByLocalIdentifier
+    0x3D, // WriteMemoryByAddress
+    0x85, // ControlDTCSetting
+    0x87, // LinkControl
+];
+
+/// Session control sub-functions that lead to reprogramming, UDS and KWP
+const PROGRAMMING_SESSIONS: [u8; 2] = [0x02, 0x85];
+/// Set in a UDS sub-function to ask the ECU not to answer, the request does the same with it
+const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+/// Classifies a command as it is sent to the adapter, e.g. `21 01` or `2EF190...`
+pub fn classify(command: &str) -> RequestClass {
+    let command: String = command.chars().filter(|c| !c.is_whitespace()).collect();
+    if command.to_uppercase().starts_with("AT") {
+        return RequestClass::Read;
+    }
+
+    let byte = |index: usize| command.get(index * 2..index * 2 + 2).and_then(|b| u8::from_str_radix(b, 16).ok());
+    match (byte(0), byte(1)) {
+        (Some(service), _) if WRITE_SERVICES.contains(&service) => RequestClass::Write,
+        // the suppress bit doesn't change the session, KWP's 0x85 is a programming session of its own
+        (Some(0x10), Some(session))
+            if PROGRAMMING_SESSIONS.contains(&session) || PROGRAMMING_SESSIONS.contains(&(session & !SUPPRESS_POSITIVE_RESPONSE)) =>
+        {
+            RequestClass::Write
+        }
+        _ => RequestClass::Read,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_services() {
+        for command in ["2E F1 90 00", "31 01 FF 00", "11 01", "14 FF FF FF", "27 01", "34 00 44", "3B 90 00", "38 01"] {
+            assert_eq!(classify(command), RequestClass::Write, "{}", command);
+        }
+    }
+
+    #[test]
+    fn programming_sessions() {
+        for command in ["10 02", "10 82", "10 85"] {
+            assert_eq!(classify(command), RequestClass::Write, "{}", command);
+        }
+    }
+
+    #[test]
+    fn read_requests() {
+        for command in ["10 03", "10 83", "21 01", "22 F190", "3E 00", "AT Z", "01 00", ""] {
+            assert_eq!(classify(command), RequestClass::Read, "{}", command);
+        }
+    }
+
+    #[test]
+    fn spacing_and_case() {
+        assert_eq!(classify("2ef19000"), RequestClass::Write);
+        assert_eq!(classify("1082"), RequestClass::Write);
+        assert_eq!(classify("10 8 2"), RequestClass::Write);
+        assert_eq!(classify("14ffffff"), RequestClass::Write);
+        assert_eq!(classify("2101"), RequestClass::Read);
+        assert_eq!(classify("22f190"), RequestClass::Read);
+        assert_eq!(classify("atz"), RequestClass::Read);
+        assert_eq!(classify(" at sh 7e4"), RequestClass::Read);
+    }
+}
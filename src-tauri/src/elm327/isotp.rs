@@ -0,0 +1,310 @@
+// Reassembly of ISO 15765-2 (ISO-TP) responses as the ELM327 prints them with CAN auto
+// formatting on. Two layouts are understood:
+//
+//  headers on (AT H1), the PCI byte is printed after the 11-bit or 29-bit header:
+//    7EC 10 26 61 02 FF FF FF FF
+//    7EC 21 C5 C5 C5 C5 C5 C5 C5
+//    7EA 06 41 00 80 00 00 01 00
+//  29-bit headers come as four bytes with spaces on (AT S1), priority first:
+//    18 DA F1 E4 03 7F 14 78
+//  with spaces off (AT S0) the header is the first 3 or 8 characters:
+//    7EC037F1478
+//    18DAF1E4037F1478
+//
+//  headers off (AT H0), the chip strips the PCI byte and numbers multi-frame lines itself:
+//    026
+//    0: 61 02 FF FF FF FF
+//    1: C5 C5 C5 C5 C5 C5 C5
+//  or, for single frames, prints just the data:
+//    41 00 80 00 00 01
+
+use std::collections::BTreeMap;
+use error_stack::Report;
+
+pub type Result<T> = error_stack::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid frame `{0}`")]
+    InvalidFrame(String),
+    #[error("Consecutive frame from {0} without a first frame")]
+    UnexpectedConsecutiveFrame(String),
+    #[error("Frames missing from {ecu}: expected sequence number {expected:X}, got {got:X}")]
+    SequenceGap { ecu: String, expected: u8, got: u8 },
+    #[error("Frames out of order from {ecu}: expected sequence number {expected:X}, got {got:X}")]
+    OutOfOrder { ecu: String, expected: u8, got: u8 },
+    #[error("Incomplete response from {ecu}: got {got} of {expected} bytes")]
+    Incomplete { ecu: String, expected: usize, got: usize },
+    #[error("Expected a response from one ECU, got {0}")]
+    UnexpectedEcuCount(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    /// Response CAN ID, `None` when headers are off
+    pub ecu: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Assembly {
+    expected_len: usize,
+    next_sequence: u8,
+    data: Vec<u8>,
+}
+
+impl Assembly {
+    fn push(&mut self, ecu: &str, sequence: u8, bytes: &[u8]) -> Result<()> {
+        if sequence != self.next_sequence {
+            // sequence numbers wrap at 16, anything within the next half is treated as skipped frames
+            let distance = sequence.wrapping_sub(self.next_sequence) & 0x0F;
+            let error = if distance < 8 {
+                Error::SequenceGap { ecu: ecu.to_string(), expected: self.next_sequence, got: sequence }
+            } else {
+                Error::OutOfOrder { ecu: ecu.to_string(), expected: self.next_sequence, got: sequence }
+            };
+            return Err(Report::new(error));
+        }
+        self.next_sequence = (self.next_sequence + 1) & 0x0F;
+        let remaining = self.expected_len.saturating_sub(self.data.len());
+        self.data.extend(&bytes[..bytes.len().min(remaining)]);
+        Ok(())
+    }
+
+    fn finish(self, ecu: &str) -> Result<Vec<u8>> {
+        if self.data.len() < self.expected_len {
+            return Err(Report::new(Error::Incomplete {
+                ecu: ecu.to_string(),
+                expected: self.expected_len,
+                got: self.data.len(),
+            }));
+        }
+        Ok(self.data)
+    }
+}
+
+fn parse_bytes(tokens: &[&str], line: &str) -> Result<Vec<u8>> {
+    tokens
+        .iter()
+        .map(|t| {
+            if t.len() != 2 {
+                return Err(Report::new(Error::InvalidFrame(line.to_string())));
+            }
+            u8::from_str_radix(t, 16).map_err(|_| Report::new(Error::InvalidFrame(line.to_string())))
+        })
+        .collect()
+}
+
+/// Splits a line into byte tokens, also accepting output with spaces off (AT S0)
+fn tokenize(line: &str) -> Vec<&str> {
+    if line.contains(' ') {
+        return line.split_whitespace().collect();
+    }
+    (0..line.len()).step_by(2).map(|i| &line[i..(i + 2).min(line.len())]).collect()
+}
+
+fn is_hex(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The first byte of a 29-bit header holds the 5 priority bits. Responses with headers off never
+/// start this low, they begin with a length, a frame index or a response SID.
+fn is_priority(value: &str) -> bool {
+    u8::from_str_radix(value, 16).is_ok_and(|value| value <= 0x1F)
+}
+
+/// CAN ID and byte tokens of a line printed with headers on, `None` for lines without a header
+fn split_header(line: &str) -> Option<(String, Vec<&str>)> {
+    if !line.chars().all(|c| c.is_ascii_hexdigit() || c == ' ') {
+        return None;
+    }
+    if line.contains(' ') {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        return match tokens[0].len() {
+            3 | 8 => Some((tokens[0].to_string(), tokens[1..].to_vec())),
+            2 if tokens.len() > 4 && is_priority(tokens[0]) => Some((tokens[..4].concat(), tokens[4..].to_vec())),
+            _ => None,
+        };
+    }
+    // bytes take two characters, only an 11-bit ID makes the length odd. A lone length line of a
+    // multi-frame message with headers off has 3 characters.
+    match line.len() {
+        len if len % 2 == 1 && len >= 5 => Some((line[..3].to_string(), tokenize(&line[3..]))),
+        len if len % 2 == 0 && len >= 12 && is_priority(&line[..2]) => Some((line[..8].to_string(), tokenize(&line[8..]))),
+        _ => None,
+    }
+}
+
+/// Reassembles every ISO-TP message in a response, one payload per responding ECU
+pub fn reassemble(response: &str) -> Result<Vec<Payload>> {
+    let lines: Vec<&str> = response.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+
+    let headers_on = lines.iter().any(|l| split_header(l).is_some());
+    if headers_on {
+        reassemble_with_headers(&lines)
+    } else {
+        reassemble_without_headers(&lines)
+    }
+}
+
+fn reassemble_with_headers(lines: &[&str]) -> Result<Vec<Payload>> {
+    let mut assemblies: BTreeMap<u32, Assembly> = BTreeMap::new();
+    let mut complete: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+
+    for line in lines {
+        let (header, tokens) = split_header(line).ok_or_else(|| Report::new(Error::InvalidFrame(line.to_string())))?;
+        let header = header.as_str();
+        let ecu = u32::from_str_radix(header, 16)
+            .map_err(|_| Report::new(Error::InvalidFrame(line.to_string())))?;
+        let bytes = parse_bytes(&tokens, line)?;
+        let Some(pci) = bytes.first() else {
+            return Err(Report::new(Error::InvalidFrame(line.to_string())));
+        };
+
+        match pci >> 4 {
+            0x0 => {
+                let len = (pci & 0x0F) as usize;
+                let data = bytes.get(1..1 + len).ok_or_else(|| Report::new(Error::InvalidFrame(line.to_string())))?;
+                complete.insert(ecu, data.to_vec());
+            }
+            0x1 => {
+                let len_low = *bytes.get(1).ok_or_else(|| Report::new(Error::InvalidFrame(line.to_string())))?;
+                let mut assembly = Assembly {
+                    expected_len: (((pci & 0x0F) as usize) << 8) | len_low as usize,
+                    next_sequence: 1,
+                    data: Vec::new(),
+                };
+                assembly.data.extend(&bytes[2..bytes.len().min(2 + assembly.expected_len)]);
+                assemblies.insert(ecu, assembly);
+            }
+            0x2 => {
+                let assembly = assemblies
+                    .get_mut(&ecu)
+                    .ok_or_else(|| Report::new(Error::UnexpectedConsecutiveFrame(header.to_string())))?;
+                assembly.push(header, pci & 0x0F, &bytes[1..])?;
+            }
+            // flow control frames from other testers on the bus
+            0x3 => {}
+            _ => return Err(Report::new(Error::InvalidFrame(line.to_string()))),
+        }
+    }
+
+    for (ecu, assembly) in assemblies {
+        let header = if ecu > 0x7FF { format!("{:08X}", ecu) } else { format!("{:03X}", ecu) };
+        complete.insert(ecu, assembly.finish(&header)?);
+    }
+
+    Ok(complete
+        .into_iter()
+        .map(|(ecu, data)| Payload { ecu: Some(ecu), data })
+        .collect())
+}
+
+fn reassemble_without_headers(lines: &[&str]) -> Result<Vec<Payload>> {
+    const ECU: &str = "ECU";
+    let mut payloads = Vec::new();
+    let mut assembly: Option<Assembly> = None;
+
+    for line in lines {
+        if let Some((index, rest)) = line.split_once(':') {
+            let sequence = u8::from_str_radix(index.trim(), 16)
+                .map_err(|_| Report::new(Error::InvalidFrame(line.to_string())))?;
+            let bytes = parse_bytes(&tokenize(rest.trim()), line)?;
+            let current = assembly
+                .as_mut()
+                .ok_or_else(|| Report::new(Error::UnexpectedConsecutiveFrame(ECU.to_string())))?;
+            current.push(ECU, sequence, &bytes)?;
+            continue;
+        }
+
+        // a lone 3 digit line announces the length of the next multi-frame message
+        if line.len() == 3 && is_hex(line) {
+            if let Some(previous) = assembly.take() {
+                payloads.push(Payload { ecu: None, data: previous.finish(ECU)? });
+            }
+            assembly = Some(Assembly {
+                expected_len: usize::from_str_radix(line, 16).unwrap_or_default(),
+                next_sequence: 0,
+                data: Vec::new(),
+            });
+            continue;
+        }
+
+        payloads.push(Payload { ecu: None, data: parse_bytes(&tokenize(line), line)? });
+    }
+
+    if let Some(assembly) = assembly {
+        payloads.push(Payload { ecu: None, data: assembly.finish(ECU)? });
+    }
+
+    Ok(payloads)
+}
+
+/// Payload of a response that only one ECU is expected to answer
+pub fn single_payload(response: &str) -> Result<Vec<u8>> {
+    let mut payloads = reassemble(response)?;
+    if payloads.len() != 1 {
+        return Err(Report::new(Error::UnexpectedEcuCount(payloads.len())));
+    }
+    Ok(payloads.remove(0).data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [u8; 10] = [0x61, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+
+    fn payload(response: &str) -> Payload {
+        let mut payloads = reassemble(response).unwrap();
+        assert_eq!(payloads.len(), 1, "{:?}", payloads);
+        payloads.remove(0)
+    }
+
+    #[test]
+    fn standard_headers_with_spaces() {
+        let response = "7EC 10 0A 61 02 01 02 03 04\n7EC 21 05 06 07 08 00 00 00\n";
+        assert_eq!(payload(response), Payload { ecu: Some(0x7EC), data: DATA.to_vec() });
+    }
+
+    #[test]
+    fn standard_headers_without_spaces() {
+        let response = "7EC100A610201020304\n7EC2105060708000000\n";
+        assert_eq!(payload(response), Payload { ecu: Some(0x7EC), data: DATA.to_vec() });
+        assert_eq!(payload("7EC037F1478"), Payload { ecu: Some(0x7EC), data: vec![0x7F, 0x14, 0x78] });
+    }
+
+    #[test]
+    fn extended_headers_with_spaces() {
+        let response = "18 DA F1 E4 10 0A 61 02 01 02 03 04\n18 DA F1 E4 21 05 06 07 08 00 00 00\n";
+        assert_eq!(payload(response), Payload { ecu: Some(0x18DAF1E4), data: DATA.to_vec() });
+        assert_eq!(payload("18DAF1E4 03 7F 14 78"), Payload { ecu: Some(0x18DAF1E4), data: vec![0x7F, 0x14, 0x78] });
+    }
+
+    #[test]
+    fn extended_headers_without_spaces() {
+        let response = "18DAF1E4100A610201020304\n18DAF1E42105060708000000\n";
+        assert_eq!(payload(response), Payload { ecu: Some(0x18DAF1E4), data: DATA.to_vec() });
+        assert_eq!(payload("18DAF1E40154"), Payload { ecu: Some(0x18DAF1E4), data: vec![0x54] });
+    }
+
+    #[test]
+    fn headers_off() {
+        let response = "00A\n0: 61 02 01 02 03 04\n1: 05 06 07 08 00 00 00\n";
+        assert_eq!(payload(response), Payload { ecu: None, data: DATA.to_vec() });
+        let response = "00A\n0:610201020304\n1:05060708000000\n";
+        assert_eq!(payload(response), Payload { ecu: None, data: DATA.to_vec() });
+        assert_eq!(payload("410080000001"), Payload { ecu: None, data: vec![0x41, 0x00, 0x80, 0x00, 0x00, 0x01] });
+        assert_eq!(payload("41 00 80 00 00 01"), Payload { ecu: None, data: vec![0x41, 0x00, 0x80, 0x00, 0x00, 0x01] });
+    }
+
+    #[test]
+    fn sequence_gap() {
+        let response = "18 DA F1 E4 10 14 61 02 01 02 03 04\n18 DA F1 E4 22 05 06 07 08 00 00 00\n";
+        let error = reassemble(response).unwrap_err();
+        assert_eq!(
+            *error.current_context(),
+            Error::SequenceGap { ecu: "18DAF1E4".to_string(), expected: 1, got: 2 }
+        );
+    }
+}
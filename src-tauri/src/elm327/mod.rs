@@ -1,21 +1,38 @@
 pub mod transport;
 pub mod error;
+pub mod capture;
+pub mod isotp;
+pub mod capabilities;
+pub mod monitor;
+pub mod guard;
+pub mod address;
 
 use transport::Transport;
+use capture::{CaptureRecord, CaptureSink, Direction};
+use capabilities::AdapterCapabilities;
+pub use address::EcuAddress;
 pub use error::{Error, Result};
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use std::{
     collections::VecDeque,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
     thread, time,
 };
-use std::io::Write;
 use error_stack::{Report, ResultExt};
 
 pub struct Elm327 {
     device: Box<dyn Transport>,
     buffer: VecDeque<u8>,
     device_name: String,
-    transport_log: std::fs::File,
+    capabilities: AdapterCapabilities,
+    capture: Box<dyn CaptureSink>,
+    started: time::Instant,
+    /// Lets requests through that `guard` classifies as writes
+    write_access: bool,
+    /// Where requests currently go, `None` after anything that may have changed it
+    address: Option<EcuAddress>,
+    /// Set when the connection is being closed, whatever is in progress fails with `NotConnected`
+    cancelled: Arc<AtomicBool>,
 }
 
 pub trait Command {
@@ -25,13 +42,17 @@ pub trait Command {
 }
 
 impl Elm327 {
-    pub fn new(transport: Box<dyn Transport>) -> Result<Self> {
-        let mut log_file = std::fs::File::create("transport.log").unwrap();
+    pub fn new(transport: Box<dyn Transport>, capture: Box<dyn CaptureSink>) -> Result<Self> {
         let mut result = Elm327 {
             buffer: VecDeque::new(),
             device: transport,
             device_name: "".to_string(),
-            transport_log: log_file,
+            capabilities: AdapterCapabilities::default(),
+            capture,
+            started: time::Instant::now(),
+            write_access: false,
+            address: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
         };
 
         result.init_device()?;
@@ -43,13 +64,71 @@ impl Elm327 {
         self.device_name.clone()
     }
 
+    pub fn get_capabilities(&self) -> &AdapterCapabilities {
+        &self.capabilities
+    }
+
+    pub fn get_transport_description(&self) -> Option<String> {
+        self.device.description()
+    }
+
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub fn has_write_access(&self) -> bool {
+        self.write_access
+    }
+
+    pub fn set_write_access(&mut self, write_access: bool) {
+        self.write_access = write_access;
+    }
+
     pub fn execute_command<J, T: Command<Response=J>>(&mut self, command: T)-> Result<J> {
         let response = self.serial_cmd(&command.serial_command())?;
         return command.parse_result(response)
     }
 
+    /// Voltage at the OBD port's pin 16, the 12V battery. The adapter measures it itself, the car
+    /// isn't woken up.
+    pub fn read_voltage(&mut self) -> Result<f64> {
+        let response = self.serial_cmd("AT RV")?;
+        let value = response.trim().trim_end_matches(['V', 'v']);
+        value
+            .parse::<f64>()
+            .change_context(Error::Other)
+            .attach_printable_lazy(|| format!("can't parse voltage {:?}", response))
+    }
+
+    /// Sends `command` to the ECU at `address`
+    pub fn execute_command_on<J, T: Command<Response=J>>(&mut self, address: &EcuAddress, command: T) -> Result<J> {
+        self.set_address(address)?;
+        self.execute_command(command)
+    }
+
+    /// Points requests at `address`, the AT commands are only sent when the target changes
+    pub fn set_address(&mut self, address: &EcuAddress) -> Result<()> {
+        if self.address.as_ref() == Some(address) {
+            return Ok(());
+        }
+        for command in address.commands(self.capabilities.receive_address_filter) {
+            match self.serial_cmd(&command) {
+                // some clones lack the flow control settings, the automatic ones work for 7E0-7E7
+                Err(e) if command.starts_with("AT FC") && *e.current_context() == Error::UnknownCommand => {
+                    debug!("Adapter doesn't support {}", command);
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+        self.address = Some(*address);
+        Ok(())
+    }
+
 
     fn reset(&mut self) -> Result<()> {
+        self.address = None;
         self.reset_ic()?;
         thread::sleep(time::Duration::from_millis(500));
         self.reset_protocol()?;
@@ -57,17 +136,7 @@ impl Elm327 {
     }
 
     pub fn get_response(&mut self) -> Result<Option<Vec<u8>>> {
-        let response = self.get_until(b'>', true)?;
-        match &response {
-            Some(response) => {
-                let _ = self.transport_log.write(format!("read: '{}'\n", Self::unescape(&std::str::from_utf8(response.as_slice()).unwrap_or("").to_string())).as_bytes());
-            }
-            None => {
-                let _ = self.transport_log.write("read: !!!!!!!ERROR!!!!!!\n".as_bytes());
-            }
-        }
-
-        Ok(response)
+        self.get_until(b'>', true)
     }
 
     fn init_device(&mut self) -> Result<()> {
@@ -80,25 +149,45 @@ impl Elm327 {
 
     /// Flush the device's buffer
     pub fn flush(&mut self) -> Result<()> {
+        // a late answer can take more than one read, a stream that never ends can't stop it
+        const MAX_READS: usize = 32;
         thread::sleep(time::Duration::from_millis(500));
-        self.read_into_queue()?;
+        for _ in 0..MAX_READS {
+            let queued = self.buffer.len();
+            self.read_into_queue()?;
+            if self.buffer.len() == queued {
+                break;
+            }
+        }
         self.buffer.clear();
         Ok(())
     }
 
     fn connect(&mut self) -> Result<()> {
         self.device.init().map_err(Elm327::map_transport_error)?;
-        self.device_name = self.serial_cmd(" ")?.replace("\n", "");
-        info!("connected to {}", &self.device_name);
+        match self.serial_cmd(" ") {
+            // a bare command on a fresh adapter may be answered with "?", it only wakes the adapter up
+            Err(e) if !e.current_context().is_response_error() => return Err(e),
+            _ => {}
+        }
         self.buffer.clear();
         self.reset()?;
 
+        self.capabilities = AdapterCapabilities::probe(self)?;
+        self.device_name = self.capabilities.name();
+        info!("connected to {}", &self.device_name);
+        self.capture.record(&CaptureRecord::Adapter {
+            t: self.started.elapsed().as_secs_f64(),
+            name: self.device_name.clone(),
+            transport: self.device.description(),
+        });
+
         Ok(())
     }
 
     fn reset_ic(&mut self) -> Result<()> {
         info!("Performing IC reset");
-        self.send_serial_str("AT Z")?;
+        self.send_serial_str(self.reset_command())?;
         debug!(
             "reset_ic: got response {:?}",
             self.get_response()?
@@ -140,6 +229,9 @@ impl Elm327 {
         let mut buf = Vec::new();
         let start = time::Instant::now();
         while start.elapsed() < TIMEOUT {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(Report::new(Error::NotConnected).attach_printable("connection closed"));
+            }
             let Some(b) = self.get_byte()? else {
                 thread::sleep(time::Duration::from_millis(50));
                 continue;
@@ -185,6 +277,7 @@ impl Elm327 {
         let mut buf = [0u8; 256];
         let len = self.device.read(&mut buf).map_err(Elm327::map_transport_error)?;
         if len > 0 {
+            self.capture.record(&CaptureRecord::data(self.started.elapsed(), Direction::Read, &buf[0..len]));
             self.buffer.extend(&buf[0..len]);
             trace!(
                 "read_into_queue: values {:?}",
@@ -196,7 +289,34 @@ impl Elm327 {
         Ok(())
     }
 
+    /// `AT Z` when the transport allows it, `AT WS` when a full reset would drop the link
+    fn reset_command(&self) -> &'static str {
+        if self.device.supports_full_reset() {
+            "AT Z"
+        } else {
+            "AT WS"
+        }
+    }
+
     pub fn serial_cmd(&mut self, cmd: &str) -> Result<String> {
+        let cmd = match cmd.replace(" ", "").to_uppercase().as_str() {
+            "ATZ" => self.reset_command(),
+            _ => cmd,
+        };
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(Report::new(Error::NotConnected).attach_printable("connection closed"));
+        }
+        if Self::changes_address(cmd) {
+            self.address = None;
+        }
+        if !self.write_access && guard::classify(cmd) == guard::RequestClass::Write {
+            warn!("Refusing to send {:?} without write access", cmd);
+            self.capture.record(&CaptureRecord::Rejected {
+                t: self.started.elapsed().as_secs_f64(),
+                command: cmd.to_string(),
+            });
+            return Err(Report::new(Error::RequestRejected).attach_printable(format!("{:?} would change the car's state", cmd)));
+        }
         self.send_serial_str(cmd)?;
         let response = self.get_response()?;
         match response {
@@ -206,23 +326,59 @@ impl Elm327 {
                     .attach_printable(format!("can't parse response to utf8 string: {:?}", response))?;
                 debug!("serial_cmd: got response '{:?}'", result);
                 let prefix = format!("{}\n", cmd);
-                if result.starts_with(&prefix) {
-                    Ok(result[prefix.len()..].to_string())
-                } else {
-                    Ok(result)
-                }
+                let result = match result.strip_prefix(&prefix) {
+                    Some(result) => result.to_string(),
+                    None => result,
+                };
+                Self::check_response(result)
+                    .attach_printable_lazy(|| format!("response to {:?}", cmd))
             }
             None => Err(Report::new(Error::Communication).attach_printable("No response received until timeout")),
         }
     }
 
+    /// Whether `cmd` resets the adapter or changes the header, filters or flow control behind
+    /// `set_address`'s back
+    fn changes_address(cmd: &str) -> bool {
+        const PREFIXES: [&str; 7] = ["ATSH", "ATCRA", "ATCF", "ATCM", "ATFC", "ATCP", "ATAR"];
+        let cmd = cmd.replace(' ', "").to_uppercase();
+        matches!(cmd.as_str(), "ATZ" | "ATWS" | "ATD") || PREFIXES.iter().any(|prefix| cmd.starts_with(prefix))
+    }
+
+    /// Turns the adapter's error messages into errors and drops the `SEARCHING...` line that
+    /// precedes the first response after a protocol search
+    fn check_response(response: String) -> Result<String> {
+        let mut lines = response.lines().filter(|l| !l.trim().is_empty()).peekable();
+        let searching = lines.next_if(|l| l.trim() == "SEARCHING...").is_some();
+
+        for line in lines.clone() {
+            let line = line.trim();
+            let error = match line {
+                "NO DATA" => Error::NoData,
+                "CAN ERROR" => Error::Can,
+                "BUFFER FULL" => Error::BufferFull,
+                "?" => Error::UnknownCommand,
+                "STOPPED" => Error::Stopped,
+                "UNABLE TO CONNECT" => Error::UnableToConnect,
+                _ if line.starts_with("BUS INIT") && line.ends_with("ERROR") => Error::BusInit,
+                _ => continue,
+            };
+            return Err(Report::new(error).attach_printable(format!("adapter responded {:?}", response)));
+        }
+
+        if !searching {
+            return Ok(response);
+        }
+        if lines.peek().is_none() {
+            return Err(Report::new(Error::Searching).attach_printable("no response after the protocol search"));
+        }
+        Ok(lines.map(|l| format!("{}\n", l)).collect())
+    }
+
     /// Function for sending a raw string, without encoding into ASCII hex
     fn send_serial_str(&mut self, data: &str) -> Result<()> {
         trace!("send_serial_str: sending {:?}", data);
 
-        let _ = self.transport_log.write(
-            format!("write: '{}'\n", Self::escape(&data.to_string())).as_bytes());
-
         let data = data.as_bytes();
 
         self.write(data)?;
@@ -233,19 +389,15 @@ impl Elm327 {
     }
 
     fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.capture.record(&CaptureRecord::data(self.started.elapsed(), Direction::Write, data));
         self.device.write(data).map_err(Elm327::map_transport_error)
     }
 
     fn map_transport_error(e: Report<transport::Error>) -> Report<Error> {
         match e.current_context() {
             transport::Error::NotConnected => e.change_context(Error::NotConnected),
+            transport::Error::ReplayMismatch(_) => e.change_context(Error::ReplayMismatch),
             _ => e.change_context(Error::Communication),
         }
     }
-    fn escape(val: &String) -> String {
-        val.replace("\r", "\\r").replace("\n", "\\n")
-    }
-    fn unescape(val: &String) -> String {
-        val.replace("\\r", "\r").replace("\\n", "\n")
-    }
 }
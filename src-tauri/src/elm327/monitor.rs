@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{thread, time};
+use error_stack::Report;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{Elm327, Error, Result};
+
+/// Which traffic the adapter passes through while monitoring
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(tag = "type", content = "address", rename_all = "snake_case")]
+pub enum MonitorTarget {
+    /// `AT MA`, everything on the bus
+    #[default]
+    All,
+    /// `AT MR hh`, frames sent to an address
+    Receiver(u8),
+    /// `AT MT hh`, frames sent by an address
+    Transmitter(u8),
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MonitorSettings {
+    #[serde(default)]
+    pub target: MonitorTarget,
+    /// `AT CF`, only frames whose ID matches the filter in the bits set in `mask` are shown
+    pub filter: Option<u32>,
+    /// `AT CM`, defaults to all 11 ID bits
+    pub mask: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CanFrame {
+    /// Seconds since the session started
+    pub t: f64,
+    pub id: u32,
+    pub data: Vec<u8>,
+}
+
+impl CanFrame {
+    /// Parses a monitored line with headers on, e.g. `4F2 00 12 A0 00 00 00 00 00`
+    fn parse(line: &str, t: f64) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        let header = tokens.next().filter(|h| h.len() == 3)?;
+        let id = u32::from_str_radix(header, 16).ok()?;
+        let data = tokens
+            .map(|t| if t.len() == 2 { u8::from_str_radix(t, 16).ok() } else { None })
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Self { t, id, data })
+    }
+}
+
+impl Elm327 {
+    const MONITOR_BATCH_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+    /// Streams raw CAN frames in batches until `stop` is set. Leaves headers on and CAN auto
+    /// formatting off, the caller has to restore its own settings afterwards.
+    pub fn monitor(&mut self, settings: &MonitorSettings, stop: &AtomicBool, mut on_frames: impl FnMut(Vec<CanFrame>)) -> Result<()> {
+        self.serial_cmd("AT H1")?;
+        self.serial_cmd("AT S1")?;
+        // show every byte of a frame instead of the ISO-TP payload
+        self.serial_cmd("AT CAF0")?;
+        match self.serial_cmd("AT CRA") {
+            Err(e) if *e.current_context() == Error::UnknownCommand => debug!("Adapter can't reset the receive address"),
+            result => {
+                result?;
+            }
+        }
+        if let Some(filter) = settings.filter {
+            self.serial_cmd(&format!("AT CF {:03X}", filter))?;
+            self.serial_cmd(&format!("AT CM {:03X}", settings.mask.unwrap_or(0x7FF)))?;
+        }
+
+        let command = match settings.target {
+            MonitorTarget::All => "AT MA".to_string(),
+            MonitorTarget::Receiver(address) => format!("AT MR {:02X}", address),
+            MonitorTarget::Transmitter(address) => format!("AT MT {:02X}", address),
+        };
+        self.start_monitor(&command)?;
+
+        let mut batch = Vec::new();
+        let mut last_batch = time::Instant::now();
+        while !stop.load(Ordering::Relaxed) && !self.cancelled.load(Ordering::Relaxed) {
+            self.read_into_queue()?;
+
+            let mut idle = true;
+            while let Some(end) = self.buffer.iter().position(|b| *b == b'\r' || *b == b'>') {
+                idle = false;
+                let raw: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&raw[..raw.len() - 1]).trim().to_string();
+
+                match line.as_str() {
+                    "" => {}
+                    "?" => return Err(Report::new(Error::UnknownCommand).attach_printable(command)),
+                    _ if line.replace(' ', "") == command.replace(' ', "") => {}
+                    _ => match CanFrame::parse(&line, self.started.elapsed().as_secs_f64()) {
+                        Some(frame) => batch.push(frame),
+                        None => trace!("monitor: skipping {:?}", line),
+                    },
+                }
+
+                if raw.last() == Some(&b'>') {
+                    // the adapter drops back to the prompt when its buffer overflows
+                    warn!("Monitor interrupted by the adapter, restarting");
+                    self.start_monitor(&command)?;
+                }
+            }
+
+            if !batch.is_empty() && last_batch.elapsed() >= Self::MONITOR_BATCH_INTERVAL {
+                on_frames(std::mem::take(&mut batch));
+                last_batch = time::Instant::now();
+            }
+            if idle {
+                thread::sleep(time::Duration::from_millis(20));
+            }
+        }
+        if !batch.is_empty() {
+            on_frames(batch);
+        }
+
+        // any byte stops the monitor
+        self.write(b"\r")?;
+        self.get_response()?;
+        self.flush()?;
+
+        Ok(())
+    }
+
+    /// Reads what the ECU sends without being asked, e.g. the final answer after a `7F xx 78`,
+    /// until `complete` accepts the lines so far. `None` when that doesn't happen within `timeout`.
+    /// The monitor keeps the receive filter of `set_address` but sends no flow control, so only
+    /// single frame answers come through.
+    pub fn listen(&mut self, timeout: time::Duration, mut complete: impl FnMut(&str) -> bool) -> Result<Option<String>> {
+        const COMMAND: &str = "AT MA";
+        self.start_monitor(COMMAND)?;
+
+        let started = time::Instant::now();
+        let mut response = String::new();
+        let result = loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(Report::new(Error::NotConnected).attach_printable("connection closed"));
+            }
+            if started.elapsed() >= timeout {
+                break None;
+            }
+            self.read_into_queue()?;
+            let Some(end) = self.buffer.iter().position(|b| *b == b'\r' || *b == b'>') else {
+                thread::sleep(time::Duration::from_millis(20));
+                continue;
+            };
+            let raw: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&raw[..raw.len() - 1]).trim().to_string();
+            if raw.last() == Some(&b'>') {
+                warn!("Monitor interrupted by the adapter, restarting");
+                self.start_monitor(COMMAND)?;
+            }
+
+            match line.as_str() {
+                "" => continue,
+                "?" => return Err(Report::new(Error::UnknownCommand).attach_printable(COMMAND)),
+                _ if line.replace(' ', "") == COMMAND.replace(' ', "") => continue,
+                _ => {}
+            }
+            response.push_str(&line);
+            response.push('\n');
+            if complete(&response) {
+                break Some(response);
+            }
+        };
+
+        // any byte stops the monitor
+        self.write(b"\r")?;
+        self.get_response()?;
+        self.flush()?;
+
+        Ok(result)
+    }
+
+    /// Sends a monitor command without the line feed, which would stop it right away
+    fn start_monitor(&mut self, command: &str) -> Result<()> {
+        trace!("start_monitor: sending {:?}", command);
+        self.write(command.as_bytes())?;
+        self.write(b"\r")
+    }
+}
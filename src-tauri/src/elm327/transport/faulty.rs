@@ -0,0 +1,429 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use error_stack::Report;
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::elm327::{
+    transport::Transport,
+};
+use super::{
+    Result
+};
+
+/// Probabilities are per byte for byte faults, per line for frame faults and per call for the rest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    pub seed: u64,
+    /// Upper bound of the random delay added to every read and write
+    pub max_latency: Duration,
+    pub split_write: f64,
+    pub drop_byte: f64,
+    pub duplicate_byte: f64,
+    pub drop_prompt: f64,
+    pub garbage: f64,
+    /// Cuts a line short, the frame loses its last bytes
+    pub truncate_frame: f64,
+    /// Sends a line after the one that follows it, the ISO-TP sequence numbers come out of order
+    pub swap_frames: f64,
+    pub disconnect: f64,
+    pub stall: f64,
+    pub stall_duration: Duration,
+}
+
+impl FaultConfig {
+    /// Parses `key=value` pairs separated by commas, e.g. `seed=42,drop_byte=0.01,stall=0.05,stall_ms=3000`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut config = FaultConfig {
+            stall_duration: Duration::from_secs(2),
+            ..Default::default()
+        };
+
+        for pair in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let invalid = |msg: String| Report::new(super::Error::InvalidParameter("faults".to_string(), msg));
+            let (key, value) = pair.split_once('=').ok_or_else(|| invalid(format!("expected key=value, got {}", pair)))?;
+            let number = value.parse::<f64>().map_err(|e| invalid(format!("{}: {}", key, e)))?;
+            match key {
+                "seed" => config.seed = number as u64,
+                "latency_ms" => config.max_latency = Duration::from_millis(number as u64),
+                "split_write" => config.split_write = number,
+                "drop_byte" => config.drop_byte = number,
+                "duplicate_byte" => config.duplicate_byte = number,
+                "drop_prompt" => config.drop_prompt = number,
+                "garbage" => config.garbage = number,
+                "truncate_frame" => config.truncate_frame = number,
+                "swap_frames" => config.swap_frames = number,
+                "disconnect" => config.disconnect = number,
+                "stall" => config.stall = number,
+                "stall_ms" => config.stall_duration = Duration::from_millis(number as u64),
+                _ => return Err(invalid(format!("unknown fault {}", key))),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Wraps a transport and corrupts the traffic the way flaky adapters do. The same seed produces
+/// the same faults for the same traffic.
+pub struct FaultInjector {
+    inner: Box<dyn Transport>,
+    config: Arc<Mutex<FaultConfig>>,
+    rng: StdRng,
+    stalled_until: Option<Instant>,
+    disconnected: bool,
+    /// Start of the line the frame faults are applied to once it ends
+    line: Vec<u8>,
+    /// Line held back to be sent after the next one
+    swapped: Option<Vec<u8>>,
+    /// Read through the frame faults, waiting for the byte faults
+    ready: VecDeque<u8>,
+}
+
+impl FaultInjector {
+    pub fn new(inner: Box<dyn Transport>, config: FaultConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            inner,
+            config: Arc::new(Mutex::new(config)),
+            stalled_until: None,
+            disconnected: false,
+            line: Vec::new(),
+            swapped: None,
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Shared handle to the faults, changes apply from the next read or write on
+    pub fn config(&self) -> Arc<Mutex<FaultConfig>> {
+        self.config.clone()
+    }
+
+    fn happens(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.gen_bool(probability.min(1.0))
+    }
+
+    fn delay(&mut self, config: &FaultConfig) {
+        if !config.max_latency.is_zero() {
+            let latency = self.rng.gen_range(Duration::ZERO..=config.max_latency);
+            thread::sleep(latency);
+        }
+    }
+
+    /// Holds the bytes back until their line ends, the prompt ends the last line of a response
+    fn frame_faults(&mut self, config: &FaultConfig, bytes: &[u8]) {
+        if config.truncate_frame == 0.0 && config.swap_frames == 0.0 && self.line.is_empty() && self.swapped.is_none() {
+            self.ready.extend(bytes);
+            return;
+        }
+
+        for b in bytes {
+            match b {
+                b'\r' if self.line.is_empty() => self.ready.push_back(*b),
+                b'\r' => {
+                    let mut line = mem::take(&mut self.line);
+                    if line.len() > 1 && self.happens(config.truncate_frame) {
+                        let at = self.rng.gen_range(1..line.len());
+                        debug!("faults: truncating line at {}", at);
+                        line.truncate(at);
+                    }
+                    line.push(*b);
+                    match self.swapped.take() {
+                        Some(swapped) => {
+                            self.ready.extend(line);
+                            self.ready.extend(swapped);
+                        }
+                        None if self.happens(config.swap_frames) => {
+                            debug!("faults: swapping line with the next one");
+                            self.swapped = Some(line);
+                        }
+                        None => self.ready.extend(line),
+                    }
+                }
+                b'>' => {
+                    self.ready.extend(self.swapped.take().unwrap_or_default());
+                    self.ready.extend(self.line.drain(..));
+                    self.ready.push_back(*b);
+                }
+                _ => self.line.push(*b),
+            }
+        }
+    }
+}
+
+impl Transport for FaultInjector {
+    fn init(&mut self) -> Result<()> {
+        self.disconnected = false;
+        self.stalled_until = None;
+        self.line.clear();
+        self.swapped = None;
+        self.ready.clear();
+        self.inner.init()
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.disconnected {
+            return Err(Report::new(super::Error::NotConnected));
+        }
+        let config = self.config.lock().unwrap().clone();
+        self.delay(&config);
+
+        if data.len() > 1 && self.happens(config.split_write) {
+            let at = self.rng.gen_range(1..data.len());
+            debug!("faults: splitting write at {}", at);
+            self.inner.write(&data[..at])?;
+            self.delay(&config);
+            return self.inner.write(&data[at..]);
+        }
+        self.inner.write(data)
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        if self.disconnected {
+            return Err(Report::new(super::Error::NotConnected));
+        }
+        let config = self.config.lock().unwrap().clone();
+        if let Some(until) = self.stalled_until {
+            if Instant::now() < until {
+                return Ok(0);
+            }
+            self.stalled_until = None;
+        }
+        if self.happens(config.stall) {
+            debug!("faults: stalling for {:?}", config.stall_duration);
+            self.stalled_until = Some(Instant::now() + config.stall_duration);
+            return Ok(0);
+        }
+        self.delay(&config);
+
+        // leave room for duplicated and garbage bytes
+        let mut buf = vec![0u8; (data.len() / 2).max(1)];
+        let len = self.inner.read(&mut buf)?;
+        self.frame_faults(&config, &buf[..len]);
+        let len = self.ready.len().min(buf.len());
+        let bytes: Vec<u8> = self.ready.drain(..len).collect();
+
+        let mut out = Vec::with_capacity(data.len());
+        for b in bytes {
+            if self.happens(config.drop_byte) || (b == b'>' && self.happens(config.drop_prompt)) {
+                continue;
+            }
+            out.push(b);
+            if self.happens(config.duplicate_byte) {
+                out.push(b);
+            }
+            if self.happens(config.garbage) {
+                out.push(self.rng.gen());
+            }
+        }
+
+        if len > 0 && self.happens(config.disconnect) {
+            // hand out part of the response, then drop the link
+            debug!("faults: disconnecting mid-response");
+            out.truncate(self.rng.gen_range(0..=out.len()));
+            self.disconnected = true;
+        }
+
+        out.truncate(data.len());
+        data[..out.len()].copy_from_slice(&out);
+        Ok(out.len())
+    }
+
+    fn connected(&self) -> bool {
+        !self.disconnected && self.inner.connected()
+    }
+
+    fn description(&self) -> Option<String> {
+        let inner = self.inner.description().map(|d| format!("{}, ", d)).unwrap_or_default();
+        Some(format!("{}fault injection, seed {}", inner, self.config.lock().unwrap().seed))
+    }
+
+    fn supports_full_reset(&self) -> bool {
+        self.inner.supports_full_reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::capture::NoCapture;
+    use crate::elm327::{isotp, Elm327, EcuAddress, Error};
+    use crate::kia::simulator::{PackState, Simulator};
+
+    const BMS: EcuAddress = EcuAddress::standard(0x7E4, 0x7EC);
+    /// Cell voltages, six frames long
+    const MULTI_FRAME: &str = "21 02";
+
+    /// Adapter behind a fault injector, the faults start once it is set up
+    fn elm327(faults: FaultConfig) -> (Elm327, Arc<Mutex<FaultConfig>>) {
+        let injector = FaultInjector::new(Box::new(Simulator::new(PackState::default())), FaultConfig::default());
+        let config = injector.config();
+        let mut elm327 = Elm327::new(Box::new(injector), Box::new(NoCapture)).unwrap();
+        set_up(&mut elm327);
+        *config.lock().unwrap() = faults;
+        (elm327, config)
+    }
+
+    fn set_up(elm327: &mut Elm327) {
+        for command in ["AT E0", "AT H1", "AT S1"] {
+            elm327.serial_cmd(command).unwrap();
+        }
+        elm327.set_address(&BMS).unwrap();
+    }
+
+    fn cell_voltages(elm327: &mut Elm327) -> isotp::Result<Vec<u8>> {
+        let response = elm327.serial_cmd(MULTI_FRAME).unwrap();
+        isotp::single_payload(&response)
+    }
+
+    /// Turns the faults off and checks the next request gets through
+    fn recovers(mut elm327: Elm327, config: Arc<Mutex<FaultConfig>>) {
+        *config.lock().unwrap() = FaultConfig::default();
+        elm327.flush().unwrap();
+        let payload = cell_voltages(&mut elm327).unwrap();
+        assert_eq!(payload.len(), 38);
+        assert_eq!(&payload[..2], &[0x61, 0x02]);
+    }
+
+    #[test]
+    fn dropped_prompt() {
+        let (mut elm327, config) = elm327(FaultConfig { drop_prompt: 1.0, ..FaultConfig::default() });
+
+        // get_until waits for the prompt until it gives up
+        let error = elm327.serial_cmd(MULTI_FRAME).unwrap_err();
+        assert_eq!(*error.current_context(), Error::Communication);
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn delay() {
+        let faults = FaultConfig { seed: 7, max_latency: Duration::from_millis(30), ..FaultConfig::default() };
+        let (mut elm327, config) = elm327(faults);
+
+        // the frames trickle in over many reads
+        for _ in 0..3 {
+            let payload = cell_voltages(&mut elm327).unwrap();
+            assert_eq!(payload.len(), 38);
+        }
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn garbage() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 3, garbage: 0.2, ..FaultConfig::default() });
+
+        let failed = match elm327.serial_cmd(MULTI_FRAME) {
+            Err(e) => *e.current_context() == Error::Communication || e.current_context().is_response_error(),
+            Ok(response) => isotp::single_payload(&response).is_err(),
+        };
+        assert!(failed, "garbage went unnoticed");
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 11, truncate_frame: 1.0, ..FaultConfig::default() });
+
+        let error = cell_voltages(&mut elm327).unwrap_err();
+        assert!(
+            matches!(error.current_context(), isotp::Error::InvalidFrame(_) | isotp::Error::Incomplete { .. }),
+            "{:?}", error
+        );
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn swapped_frames() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 5, swap_frames: 1.0, ..FaultConfig::default() });
+
+        let error = cell_voltages(&mut elm327).unwrap_err();
+        assert!(
+            matches!(error.current_context(), isotp::Error::OutOfOrder { .. } | isotp::Error::UnexpectedConsecutiveFrame(_)),
+            "{:?}", error
+        );
+        recovers(elm327, config);
+    }
+
+    /// A corrupted answer has to be noticed, it mustn't pass for a different one
+    fn succeeds_or_fails_cleanly(elm327: &mut Elm327) {
+        match elm327.serial_cmd(MULTI_FRAME) {
+            Ok(response) => match isotp::single_payload(&response) {
+                Ok(payload) => {
+                    assert_eq!(payload.len(), 38);
+                    assert_eq!(&payload[..2], &[0x61, 0x02]);
+                }
+                Err(e) => debug!("corrupted answer: {:?}", e),
+            },
+            Err(e) => debug!("failed request: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn split_write() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 13, split_write: 1.0, ..FaultConfig::default() });
+
+        // the adapter puts the command back together
+        for _ in 0..3 {
+            let payload = cell_voltages(&mut elm327).unwrap();
+            assert_eq!(payload.len(), 38);
+        }
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn dropped_byte() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 17, drop_byte: 0.01, ..FaultConfig::default() });
+
+        for _ in 0..5 {
+            succeeds_or_fails_cleanly(&mut elm327);
+        }
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn duplicated_byte() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 19, duplicate_byte: 0.01, ..FaultConfig::default() });
+
+        for _ in 0..5 {
+            succeeds_or_fails_cleanly(&mut elm327);
+        }
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn disconnect() {
+        let (mut elm327, config) = elm327(FaultConfig { seed: 23, disconnect: 1.0, ..FaultConfig::default() });
+
+        let error = elm327.serial_cmd(MULTI_FRAME).unwrap_err();
+        assert_eq!(*error.current_context(), Error::NotConnected);
+
+        // connecting again resets the adapter
+        *config.lock().unwrap() = FaultConfig::default();
+        elm327.init_device().unwrap();
+        set_up(&mut elm327);
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn stall() {
+        // a bit longer than get_until waits for the prompt
+        let faults = FaultConfig { stall: 1.0, stall_duration: Duration::from_millis(5200), ..FaultConfig::default() };
+        let (mut elm327, config) = elm327(faults);
+
+        let error = elm327.serial_cmd(MULTI_FRAME).unwrap_err();
+        assert_eq!(*error.current_context(), Error::Communication);
+        // the stalled answer arrives late and is flushed
+        recovers(elm327, config);
+    }
+
+    #[test]
+    fn parses_frame_faults() {
+        let config = FaultConfig::parse("seed=1,truncate_frame=0.5,swap_frames=0.25").unwrap();
+        assert_eq!(config.truncate_frame, 0.5);
+        assert_eq!(config.swap_frames, 0.25);
+    }
+}
@@ -1,9 +1,13 @@
 mod wifi;
 mod serial;
+mod replay;
+mod faulty;
 
 #[allow(unused_imports)]
 pub use wifi::WiFi;
 pub use serial::Serial;
+pub use replay::{Replay, ReplayMode};
+pub use faulty::{FaultConfig, FaultInjector};
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Connection failed")]
@@ -14,17 +18,27 @@ pub enum Error {
     IO(#[from] std::io::Error),
     #[error("Invalid parameter {0}: `{1}`")]
     InvalidParameter(String, String),
+    #[error("Replay mismatch: {0}")]
+    ReplayMismatch(String),
     #[error("Internal error")]
     Other,
 }
 
 
-type Result<T> = error_stack::Result<T, Error>;
+pub type Result<T> = error_stack::Result<T, Error>;
 
-pub trait Transport : Send + Sync {
+pub trait Transport : Send {
     fn init(&mut self) -> Result<()>;
     fn write(&mut self, data: &[u8]) -> Result<()>;
     fn read(&mut self, data: &mut [u8]) -> Result<usize>;
     fn connected(&self) -> bool;
+    /// Extra details about the link, shown next to the adapter name
+    fn description(&self) -> Option<String> {
+        None
+    }
+    /// Whether the adapter may be sent `AT Z`. When false `AT WS` is used instead.
+    fn supports_full_reset(&self) -> bool {
+        true
+    }
 }
 
@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use error_stack::{Report, ResultExt};
+use log::debug;
+
+use crate::elm327::{
+    capture::{CaptureRecord, Direction},
+    transport::Transport,
+};
+use super::{
+    Result
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayMode {
+    /// Commands must be written in exactly the recorded order
+    Strict,
+    /// Each command is answered with a recorded response to the same command text
+    Lenient,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Write(String),
+    Read(Option<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Exchange {
+    command: String,
+    /// Bytes the adapter sent back, including the prompt. `None` when it never answered.
+    response: Option<String>,
+}
+
+/// Answers commands with the responses recorded in a capture file, either the JSON lines written
+/// by `capture::FileCapture` or the older `transport.log` format
+pub struct Replay {
+    exchanges: Vec<Exchange>,
+    mode: ReplayMode,
+    position: usize,
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    connected: bool,
+}
+
+impl Replay {
+    pub fn from_file(path: impl AsRef<Path>, mode: ReplayMode) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Report::new(super::Error::InvalidParameter("path".to_string(), e.to_string())))
+            .attach_printable(format!("Can't read capture {}", path.display()))?;
+
+        Self::from_log(&content, mode)
+    }
+
+    pub fn from_log(log: &str, mode: ReplayMode) -> Result<Self> {
+        let is_json = log.trim_start().starts_with('{');
+        let exchanges = if is_json {
+            Self::parse_capture(log)?
+        } else {
+            Self::parse_transport_log(log)
+        };
+
+        if exchanges.is_empty() {
+            return Err(Report::new(super::Error::InvalidParameter("path".to_string(), "capture contains no commands".to_string())));
+        }
+
+        Ok(Self {
+            exchanges,
+            mode,
+            position: 0,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            connected: false,
+        })
+    }
+
+    fn parse_capture(log: &str) -> Result<Vec<Exchange>> {
+        let mut exchanges = Vec::new();
+        let mut command = Vec::new();
+        // command being answered and the bytes received for it so far
+        let mut pending: Option<(String, Vec<u8>)> = None;
+
+        for (number, line) in log.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let record: CaptureRecord = serde_json::from_str(line)
+                .map_err(|e| Report::new(super::Error::InvalidParameter("path".to_string(), e.to_string())))
+                .attach_printable(format!("Bad capture record on line {}", number + 1))?;
+            let CaptureRecord::Data { direction, .. } = &record else {
+                continue;
+            };
+            let bytes = record.bytes().ok_or_else(|| {
+                Report::new(super::Error::InvalidParameter("path".to_string(), format!("bad data on line {}", number + 1)))
+            })?;
+
+            match direction {
+                Direction::Write => {
+                    for b in bytes {
+                        match b {
+                            b'\r' => {
+                                if let Some(exchange) = pending.take() {
+                                    exchanges.push(Self::finish_exchange(exchange));
+                                }
+                                pending = Some((String::from_utf8_lossy(&command).to_string(), Vec::new()));
+                                command.clear();
+                            }
+                            b'\n' => {}
+                            _ => command.push(b),
+                        }
+                    }
+                }
+                Direction::Read => {
+                    // anything after the prompt is unsolicited and flushed by the app
+                    if let Some((_, response)) = pending.as_mut().filter(|(_, r)| !r.contains(&b'>')) {
+                        response.extend(bytes);
+                    }
+                }
+            }
+        }
+        if let Some(exchange) = pending.take() {
+            exchanges.push(Self::finish_exchange(exchange));
+        }
+
+        Ok(exchanges)
+    }
+
+    fn finish_exchange((command, response): (String, Vec<u8>)) -> Exchange {
+        let response = match response.iter().position(|b| *b == b'>') {
+            Some(prompt) => Some(String::from_utf8_lossy(&response[..=prompt]).to_string()),
+            None if response.is_empty() => None,
+            None => Some(String::from_utf8_lossy(&response).to_string()),
+        };
+        Exchange { command, response }
+    }
+
+    fn parse_transport_log(log: &str) -> Vec<Exchange> {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+        for entry in Self::parse_entries(log) {
+            match entry {
+                Entry::Write(command) => exchanges.push(Exchange { command, response: None }),
+                Entry::Read(response) => match exchanges.last_mut() {
+                    // the log stored responses without the prompt and with \r turned into \n
+                    Some(exchange) if exchange.response.is_none() => {
+                        exchange.response = response.map(|r| format!("{}>", r.replace('\n', "\r")));
+                    }
+                    // reads without a preceding write are flushes of unsolicited data
+                    _ => {}
+                },
+            }
+        }
+        exchanges
+    }
+
+    /// Splits the log into entries. Older captures wrote responses unescaped, so an entry runs
+    /// until the next line starting with `write:` or `read:`.
+    fn parse_entries(log: &str) -> Vec<Entry> {
+        let mut raw_entries: Vec<String> = Vec::new();
+        for line in log.split('\n') {
+            if line.starts_with("write: ") || line.starts_with("read: ") || raw_entries.is_empty() {
+                raw_entries.push(line.to_string());
+            } else if let Some(entry) = raw_entries.last_mut() {
+                entry.push('\n');
+                entry.push_str(line);
+            }
+        }
+
+        raw_entries
+            .into_iter()
+            .filter_map(|entry| {
+                let entry = entry.trim_end_matches('\n');
+                if let Some(command) = entry.strip_prefix("write: ") {
+                    return Some(Entry::Write(Self::unquote(command)));
+                }
+                if let Some(response) = entry.strip_prefix("read: ") {
+                    if !response.starts_with('\'') {
+                        // read: !!!!!!!ERROR!!!!!! - the adapter didn't answer
+                        return Some(Entry::Read(None));
+                    }
+                    return Some(Entry::Read(Some(Self::unquote(response))));
+                }
+                None
+            })
+            .collect()
+    }
+
+    fn unquote(value: &str) -> String {
+        let value = value.strip_prefix('\'').unwrap_or(value);
+        let value = value.strip_suffix('\'').unwrap_or(value);
+        value.replace("\\r", "\r").replace("\\n", "\n")
+    }
+
+    fn normalize(command: &str) -> String {
+        command.replace(' ', "").to_uppercase()
+    }
+
+    fn find_exchange(&mut self, command: &str) -> Result<Option<Exchange>> {
+        match self.mode {
+            ReplayMode::Strict => {
+                let exchange = self.exchanges.get(self.position).ok_or_else(|| {
+                    Report::new(super::Error::ReplayMismatch(format!("unexpected command {:?} after the end of the capture", command)))
+                })?;
+                if exchange.command != command {
+                    return Err(Report::new(super::Error::ReplayMismatch(format!(
+                        "command #{} is {:?}, capture has {:?}",
+                        self.position, command, exchange.command
+                    ))));
+                }
+                self.position += 1;
+                Ok(Some(exchange.clone()))
+            }
+            ReplayMode::Lenient => {
+                let normalized = Self::normalize(command);
+                let matches = |e: &Exchange| Self::normalize(&e.command) == normalized;
+                // prefer the next recorded answer so repeated commands replay the recorded sequence
+                let index = self.exchanges[self.position..]
+                    .iter()
+                    .position(matches)
+                    .map(|i| i + self.position)
+                    .or_else(|| self.exchanges.iter().position(matches));
+
+                Ok(index.map(|i| {
+                    self.position = i + 1;
+                    self.exchanges[i].clone()
+                }))
+            }
+        }
+    }
+
+    fn respond(&mut self, command: &str) -> Result<()> {
+        let response = match self.find_exchange(command)? {
+            Some(exchange) => exchange.response,
+            None => {
+                debug!("replay: {:?} is not in the capture", command);
+                Some("?\r\r>".to_string())
+            }
+        };
+
+        // a recorded timeout is replayed as silence
+        if let Some(response) = response {
+            self.output.extend(response.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Replay {
+    fn init(&mut self) -> Result<()> {
+        self.position = 0;
+        self.input.clear();
+        self.output.clear();
+        self.connected = true;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if !self.connected {
+            return Err(Report::new(super::Error::NotConnected));
+        }
+
+        for b in data {
+            match b {
+                b'\r' => {
+                    let command = String::from_utf8_lossy(&self.input).to_string();
+                    self.input.clear();
+                    self.respond(&command)?;
+                }
+                b'\n' => {}
+                _ => self.input.push(*b),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        if !self.connected {
+            return Err(Report::new(super::Error::NotConnected));
+        }
+
+        let len = data.len().min(self.output.len());
+        for (i, b) in self.output.drain(..len).enumerate() {
+            data[i] = b;
+        }
+        Ok(len)
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(match self.mode {
+            ReplayMode::Strict => "strict replay".to_string(),
+            ReplayMode::Lenient => "replay".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::transport::Error;
+
+    /// Written by versions before the JSON captures. The multi-frame response was stored
+    /// unescaped and spans lines, the failed `01 00` never got an answer.
+    const TRANSPORT_LOG: &str = "\
+read: 'unsolicited\\r'
+write: 'AT Z'
+read: '\\r\\rELM327 v1.5\\r\\r'
+write: '21 01'
+read: '7EC 10 3E 61 01 FF FF FF FF
+7EC 21 BC 26 48 27 10 A0 FF
+7EC 22 85 0E 69 10 FE 0E 0F
+'
+write: '01 00'
+read: !!!!!!!ERROR!!!!!!
+write: 'AT RV'
+read: '14.1V\\r\\r'
+";
+
+    fn replay(mode: ReplayMode) -> Replay {
+        let mut replay = Replay::from_log(TRANSPORT_LOG, mode).unwrap();
+        replay.init().unwrap();
+        replay
+    }
+
+    /// Everything the replay sends back for `command`
+    fn answer(replay: &mut Replay, command: &str) -> Result<String> {
+        replay.write(format!("{}\r", command).as_bytes())?;
+        let mut buf = [0u8; 256];
+        let len = replay.read(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
+
+    #[test]
+    fn parses_transport_log() {
+        let exchanges = Replay::parse_transport_log(TRANSPORT_LOG);
+        let commands: Vec<&str> = exchanges.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["AT Z", "21 01", "01 00", "AT RV"]);
+        assert_eq!(exchanges[0].response.as_deref(), Some("\r\rELM327 v1.5\r\r>"));
+        assert_eq!(
+            exchanges[1].response.as_deref(),
+            Some("7EC 10 3E 61 01 FF FF FF FF\r7EC 21 BC 26 48 27 10 A0 FF\r7EC 22 85 0E 69 10 FE 0E 0F\r>")
+        );
+        assert_eq!(exchanges[2].response, None);
+        assert_eq!(exchanges[3].response.as_deref(), Some("14.1V\r\r>"));
+    }
+
+    #[test]
+    fn replays_in_order() {
+        let mut replay = replay(ReplayMode::Strict);
+        assert_eq!(answer(&mut replay, "AT Z").unwrap(), "\r\rELM327 v1.5\r\r>");
+        assert!(answer(&mut replay, "21 01").unwrap().starts_with("7EC 10 3E"));
+        // the recorded timeout stays silent
+        assert_eq!(answer(&mut replay, "01 00").unwrap(), "");
+        assert_eq!(answer(&mut replay, "AT RV").unwrap(), "14.1V\r\r>");
+    }
+
+    #[test]
+    fn lenient_out_of_order() {
+        let mut replay = replay(ReplayMode::Lenient);
+        assert_eq!(answer(&mut replay, "AT RV").unwrap(), "14.1V\r\r>");
+        // spacing and case don't matter
+        assert!(answer(&mut replay, "2101").unwrap().starts_with("7EC 10 3E"));
+        assert_eq!(answer(&mut replay, "at z").unwrap(), "\r\rELM327 v1.5\r\r>");
+        assert_eq!(answer(&mut replay, "09 02").unwrap(), "?\r\r>");
+    }
+
+    #[test]
+    fn strict_out_of_order() {
+        let mut replay = replay(ReplayMode::Strict);
+        let error = answer(&mut replay, "AT RV").unwrap_err();
+        assert!(
+            matches!(error.current_context(), Error::ReplayMismatch(message) if message.contains("AT RV") && message.contains("AT Z")),
+            "{:?}", error
+        );
+    }
+
+    #[test]
+    fn strict_after_the_end() {
+        let mut replay = replay(ReplayMode::Strict);
+        for command in ["AT Z", "21 01", "01 00", "AT RV"] {
+            answer(&mut replay, command).unwrap();
+        }
+        assert!(matches!(answer(&mut replay, "AT RV").unwrap_err().current_context(), Error::ReplayMismatch(_)));
+    }
+}
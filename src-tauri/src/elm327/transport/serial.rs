@@ -1,8 +1,10 @@
 use std::io;
 use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::thread;
 use error_stack::{Report, ResultExt};
+use log::{debug, info};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
 
 use crate::elm327::{
     transport::Transport,
@@ -11,62 +13,226 @@ use super::{
     Result
 };
 
+/// Rates the adapters we've seen ship with, most common first
+const PROBE_BAUD_RATES: [u32; 6] = [38400, 9600, 115200, 57600, 230400, 500000];
+/// Rates tried with `AT BRD` after the adapter answered, fastest first
+const UPGRADE_BAUD_RATES: [u32; 4] = [500000, 230400, 115200, 57600];
+/// `AT BRT` value in 4 ms units, the default 75 ms is too short for some USB bridges to reconfigure
+const BAUD_RATE_SWITCH_TIMEOUT: u8 = 0x32;
+
+#[derive(Debug, Clone)]
+pub struct SerialSettings {
+    /// Fixed baud rate, or `None` to probe the common ELM327 rates until `ATI` answers
+    pub baud_rate: Option<u32>,
+    /// Switch to the fastest rate the chip accepts with `AT BRD` once connected
+    pub upgrade_baud_rate: bool,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// How long a single read waits for data before reporting that nothing is available
+    pub read_timeout: Duration,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self {
+            baud_rate: None,
+            upgrade_baud_rate: true,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            read_timeout: Duration::from_millis(10),
+        }
+    }
+}
 
 pub struct Serial {
-    socket_addr: SocketAddr,
-    stream: Option<TcpStream>,
+    port_name: String,
+    settings: SerialSettings,
+    port: Option<Box<dyn SerialPort>>,
+    baud_rate: u32,
+    baud_rate_upgraded: bool,
 }
 
 impl Serial {
-    pub fn new(addr: &str) -> Result<Self> {
-        let mut socket_addrs = addr
-            .to_socket_addrs()
-            .attach_printable(format!("Invalid address: {}", addr))
-            .map_err(|e| Report::new(super::Error::InvalidParameter("addr".to_string(), e.to_string())))?;
-
-        let socket_addr = socket_addrs
-            .next()
-            .ok_or_else(|| Report::new(super::Error::InvalidParameter("addr".to_string(), "empty address".to_string())))?;
+    pub fn new(port_name: &str) -> Result<Self> {
+        Self::with_settings(port_name, SerialSettings::default())
+    }
 
+    pub fn with_settings(port_name: &str, settings: SerialSettings) -> Result<Self> {
+        if port_name.trim().is_empty() {
+            return Err(Report::new(super::Error::InvalidParameter("port".to_string(), "empty port name".to_string())));
+        }
+        if settings.baud_rate == Some(0) {
+            return Err(Report::new(super::Error::InvalidParameter("baud_rate".to_string(), "baud rate must be positive".to_string())));
+        }
 
         Ok(Self {
-            socket_addr,
-            stream: None,
+            port_name: port_name.trim().to_string(),
+            baud_rate: settings.baud_rate.unwrap_or(PROBE_BAUD_RATES[0]),
+            settings,
+            port: None,
+            baud_rate_upgraded: false,
         })
     }
-    pub fn set_write_timeout(&mut self, timeout: Duration) -> Result<()> {
-        if let Some(stream) = &mut self.stream {
-            stream
-                .set_write_timeout(Some(timeout))
-                .attach_printable("Failed to set write timeout")
-                .change_context(super::Error::Other)?;
 
-            return Ok(())
+    fn map_serial_error(e: serialport::Error) -> Report<super::Error> {
+        let context = match e.kind() {
+            serialport::ErrorKind::NoDevice => super::Error::ConnectionFailed,
+            serialport::ErrorKind::InvalidInput => super::Error::InvalidParameter("port".to_string(), e.to_string()),
+            serialport::ErrorKind::Io(kind) => super::Error::IO(io::Error::new(kind, e.to_string())),
+            serialport::ErrorKind::Unknown => super::Error::Other,
+        };
+        Report::new(context).attach_printable(format!("Serial port error: {}", e))
+    }
+
+    /// Finds the rate the adapter is listening on by sending `ATI` until it answers
+    fn detect_baud_rate(port: &mut Box<dyn SerialPort>) -> Result<u32> {
+        for rate in PROBE_BAUD_RATES {
+            debug!("detect_baud_rate: probing {} baud", rate);
+            port
+                .set_baud_rate(rate)
+                .map_err(Serial::map_serial_error)?;
+            // the first carriage return flushes whatever garbage the adapter received at the wrong rate
+            Serial::exchange(port, "", Duration::from_millis(200))?;
+            let response = Serial::exchange(port, "ATI", Duration::from_millis(500))?;
+            if response.contains("ELM") {
+                info!("Adapter answered at {} baud: {:?}", rate, response.trim());
+                return Ok(rate);
+            }
         }
 
-        return Err(Report::new(super::Error::NotConnected));
+        Err(Report::new(super::Error::ConnectionFailed)
+            .attach_printable(format!("Adapter did not answer ATI at any of {:?} baud", PROBE_BAUD_RATES)))
+    }
+
+    /// Performs the `AT BRD` handshake. Returns false if the chip refused or didn't confirm the new
+    /// rate, in which case the port is switched back to `current` as the chip reverts on its own.
+    fn switch_baud_rate(port: &mut Box<dyn SerialPort>, current: u32, target: u32) -> Result<bool> {
+        let divisor = (4_000_000 + target / 2) / target;
+        if divisor > 0xFF {
+            return Ok(false);
+        }
+
+        // older clones don't know AT BRT and keep the default timeout
+        let response = Serial::exchange(port, &format!("AT BRT {:02X}", BAUD_RATE_SWITCH_TIMEOUT), Duration::from_millis(500))?;
+        if !response.contains("OK") {
+            debug!("switch_baud_rate: AT BRT {:02X} refused: {:?}", BAUD_RATE_SWITCH_TIMEOUT, response);
+        }
+
+        Serial::write_str(port, &format!("AT BRD {:02X}", divisor))?;
+        let response = Serial::read_until(port, |r| r.contains("OK") || r.contains('?') || r.contains('>'), Duration::from_millis(500))?;
+        if !response.contains("OK") {
+            debug!("switch_baud_rate: AT BRD {:02X} refused: {:?}", divisor, response);
+            Serial::read_until(port, |r| r.contains('>'), Duration::from_millis(200))?;
+            return Ok(false);
+        }
+
+        port
+            .set_baud_rate(target)
+            .map_err(Serial::map_serial_error)?;
+
+        // at the new rate the chip sends its ATI string and waits AT BRT time for a carriage return
+        let greeting = Serial::read_until(port, |r| r.contains("ELM") && r.ends_with('\r'), Duration::from_millis(500))?;
+        if greeting.contains("ELM") {
+            Serial::write_str(port, "")?;
+            let confirmation = Serial::read_until(port, |r| r.contains('>'), Duration::from_millis(500))?;
+            if confirmation.contains("OK") {
+                return Ok(true);
+            }
+        }
+
+        debug!("switch_baud_rate: {} baud not confirmed, falling back to {}", target, current);
+        port
+            .set_baud_rate(current)
+            .map_err(Serial::map_serial_error)?;
+        Serial::read_until(port, |r| r.contains('>'), Duration::from_millis(500))?;
+        port
+            .clear(serialport::ClearBuffer::All)
+            .map_err(Serial::map_serial_error)?;
+
+        Ok(false)
+    }
+
+    fn exchange(port: &mut Box<dyn SerialPort>, cmd: &str, timeout: Duration) -> Result<String> {
+        Serial::write_str(port, cmd)?;
+        Serial::read_until(port, |r| r.contains('>'), timeout)
+    }
+
+    fn write_str(port: &mut Box<dyn SerialPort>, cmd: &str) -> Result<()> {
+        port
+            .write_all(format!("{}\r", cmd).as_bytes())
+            .and_then(|_| port.flush())
+            .map_err(|e| Report::new(super::Error::IO(e)))
+    }
+
+    fn read_until(port: &mut Box<dyn SerialPort>, done: impl Fn(&str) -> bool, timeout: Duration) -> Result<String> {
+        let mut response = Vec::new();
+        let mut buf = [0u8; 64];
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            match port.read(&mut buf) {
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(e) => return Err(Report::new(super::Error::IO(e))),
+            }
+            if done(&String::from_utf8_lossy(&response)) {
+                break;
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&response).to_string())
     }
 }
 
 impl Transport for Serial {
     fn init(&mut self) -> Result<()> {
-        let stream = TcpStream::connect_timeout(&self.socket_addr, Duration::from_secs(2))
-            .attach_printable("Can't connect to tcp socket")
-            .change_context(super::Error::ConnectionFailed)?;
+        let mut port = serialport::new(&self.port_name, self.baud_rate)
+            .data_bits(self.settings.data_bits)
+            .parity(self.settings.parity)
+            .stop_bits(self.settings.stop_bits)
+            .flow_control(self.settings.flow_control)
+            .timeout(self.settings.read_timeout)
+            .open()
+            .map_err(Serial::map_serial_error)
+            .attach_printable(format!("Can't open serial port {}", self.port_name))?;
 
-        stream.
-            set_nonblocking(true)
-            .attach_printable("Failed to set non-blocking mode")
-            .change_context(super::Error::Other)?;
+        port
+            .clear(serialport::ClearBuffer::All)
+            .map_err(Serial::map_serial_error)
+            .attach_printable("Failed to clear serial port buffers")?;
 
-        self.stream = Some(stream);
+        self.baud_rate = match self.settings.baud_rate {
+            Some(rate) => rate,
+            None => Serial::detect_baud_rate(&mut port)?,
+        };
+        self.baud_rate_upgraded = false;
+
+        if self.settings.upgrade_baud_rate {
+            for rate in UPGRADE_BAUD_RATES.iter().filter(|r| **r > self.baud_rate) {
+                if Serial::switch_baud_rate(&mut port, self.baud_rate, *rate)? {
+                    info!("Switched adapter from {} to {} baud", self.baud_rate, rate);
+                    self.baud_rate = *rate;
+                    self.baud_rate_upgraded = true;
+                    break;
+                }
+            }
+        }
+
+        self.port = Some(port);
         Ok(())
     }
     fn write(&mut self, data: &[u8]) -> Result<()> {
-        if let Some(stream) = &mut self.stream {
-            return match stream.write_all(data) {
+        if let Some(port) = &mut self.port {
+            return match port.write_all(data).and_then(|_| port.flush()) {
                 Ok(_) => Ok(()),
                 Err(ref e) if e.kind() == io::ErrorKind::WriteZero || e.kind() == io::ErrorKind::BrokenPipe => {
+                    self.port = None;
+
                     Err(Report::new(super::Error::NotConnected))
                 }
                 Err(e) => {
@@ -79,18 +245,19 @@ impl Transport for Serial {
     }
 
     fn read(&mut self, data: &mut [u8]) -> Result<usize> {
-        if let Some(stream) = &mut self.stream {
-            return match stream.read(data) {
-                Ok(0) => {
-                    self.stream = None;
-
-                    return Err(Report::new(super::Error::NotConnected));
-                }
+        if let Some(port) = &mut self.port {
+            return match port.read(data) {
                 Ok(n) => Ok(n),
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
                     // Treat timeout as zero bytes read, indicating no data available
                     Ok(0)
                 }
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    // the device was unplugged
+                    self.port = None;
+
+                    Err(Report::new(super::Error::NotConnected))
+                }
                 Err(e) => Err(Report::new(super::Error::IO(e)))
             };
         }
@@ -98,6 +265,165 @@ impl Transport for Serial {
         return Err(Report::new(super::Error::NotConnected));
     }
     fn connected(&self) -> bool {
-        self.stream.is_some()
+        self.port.is_some()
+    }
+    fn description(&self) -> Option<String> {
+        Some(format!("{} baud", self.baud_rate))
+    }
+    fn supports_full_reset(&self) -> bool {
+        // AT Z puts the chip back to its power-on baud rate, which we would no longer be listening on
+        !self.baud_rate_upgraded
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use serialport::TTYPort;
+    use crate::elm327::capture::NoCapture;
+    use crate::elm327::Elm327;
+    use crate::kia::simulator::{PackState, Simulator};
+
+    /// The simulator behind the master side of a pty, the slave side is where `Serial` connects
+    struct Adapter {
+        port_name: String,
+        stop: Arc<AtomicBool>,
+        bridge: Option<thread::JoinHandle<()>>,
+        /// Every command line the adapter received
+        commands: Arc<Mutex<Vec<String>>>,
+        // the pty goes away with its last open slave
+        _slave: TTYPort,
+    }
+
+    /// Where the adapter is in the `AT BRD` handshake
+    enum BaudRateSwitch {
+        None,
+        /// Sends its ATI string at the new rate once the host had time to follow
+        Greeting(Instant),
+        /// Waits for the host's carriage return at the new rate
+        Confirmation,
+    }
+
+    impl Adapter {
+        /// Refuses `AT BRT` and `AT BRD` like the simulator does
+        fn start() -> Self {
+            Self::spawn(false)
+        }
+
+        /// Takes any `AT BRD` like a genuine v1.4+ chip. A pty has no real rate, only the handshake
+        /// is played.
+        fn start_switching() -> Self {
+            Self::spawn(true)
+        }
+
+        fn spawn(switch_baud_rate: bool) -> Self {
+            let (mut master, slave) = TTYPort::pair().unwrap();
+            master.set_timeout(Duration::from_millis(5)).unwrap();
+            let port_name = slave.name().unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let commands = Arc::new(Mutex::new(Vec::new()));
+            let bridge = {
+                let stop = stop.clone();
+                let commands = commands.clone();
+                thread::spawn(move || {
+                    let mut simulator = Simulator::new(PackState::default());
+                    simulator.init().unwrap();
+                    let mut line = Vec::new();
+                    let mut switch = BaudRateSwitch::None;
+                    let mut buf = [0u8; 256];
+                    while !stop.load(Ordering::Relaxed) {
+                        let n = master.read(&mut buf).unwrap_or(0);
+                        for b in &buf[..n] {
+                            if *b != b'\r' {
+                                line.push(*b);
+                                continue;
+                            }
+                            let command = String::from_utf8_lossy(&line).trim().to_string();
+                            line.clear();
+                            commands.lock().unwrap().push(command.clone());
+                            match switch {
+                                BaudRateSwitch::Confirmation => {
+                                    master.write_all(b"OK\r\r>").unwrap();
+                                    switch = BaudRateSwitch::None;
+                                }
+                                _ if switch_baud_rate && command.starts_with("AT BRT") => master.write_all(b"OK\r\r>").unwrap(),
+                                _ if switch_baud_rate && command.starts_with("AT BRD") => {
+                                    master.write_all(b"OK\r").unwrap();
+                                    switch = BaudRateSwitch::Greeting(Instant::now() + Duration::from_millis(50));
+                                }
+                                _ => simulator.write(format!("{}\r", command).as_bytes()).unwrap(),
+                            }
+                        }
+                        if matches!(switch, BaudRateSwitch::Greeting(at) if Instant::now() >= at) {
+                            master.write_all(b"ELM327 v1.5\r").unwrap();
+                            switch = BaudRateSwitch::Confirmation;
+                        }
+                        let n = simulator.read(&mut buf).unwrap();
+                        master.write_all(&buf[..n]).unwrap();
+                    }
+                })
+            };
+            Self { port_name, stop, bridge: Some(bridge), commands, _slave: slave }
+        }
+    }
+
+    impl Drop for Adapter {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(bridge) = self.bridge.take() {
+                bridge.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn connects_through_elm327() {
+        let adapter = Adapter::start();
+        let serial = Serial::new(&adapter.port_name).unwrap();
+        let mut elm327 = Elm327::new(Box::new(serial), Box::new(NoCapture)).unwrap();
+
+        assert!(elm327.get_connected_device_name().contains("ELM327"), "{}", elm327.get_connected_device_name());
+        // found by probing, the simulator refuses AT BRD so the rate stays
+        assert_eq!(elm327.get_transport_description(), Some("38400 baud".to_string()));
+        let response = elm327.serial_cmd("01 00").unwrap();
+        assert!(response.contains("41 00"), "{:?}", response);
+    }
+
+    #[test]
+    fn read_times_out() {
+        let adapter = Adapter::start();
+        let settings = SerialSettings {
+            read_timeout: Duration::from_millis(50),
+            ..SerialSettings::default()
+        };
+        let mut serial = Serial::with_settings(&adapter.port_name, settings).unwrap();
+        serial.init().unwrap();
+        assert!(serial.connected());
+
+        // the probe's prompt may still be on its way
+        let mut buf = [0u8; 64];
+        while serial.read(&mut buf).unwrap() > 0 {}
+
+        let start = Instant::now();
+        assert_eq!(serial.read(&mut buf).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(40), "{:?}", start.elapsed());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn switches_baud_rate() {
+        let adapter = Adapter::start_switching();
+        let serial = Serial::new(&adapter.port_name).unwrap();
+        let elm327 = Elm327::new(Box::new(serial), Box::new(NoCapture)).unwrap();
+
+        // the fastest rate is taken first, 4 MHz / 8
+        assert_eq!(elm327.get_transport_description(), Some("500000 baud".to_string()));
+        let commands = adapter.commands.lock().unwrap().clone();
+        let switch = commands.iter().position(|c| c == "AT BRT 32").unwrap();
+        assert_eq!(commands[switch + 1], "AT BRD 08");
+        // AT Z would send the chip back to the rate it started at
+        let after: Vec<&String> = commands[switch..].iter().filter(|c| c.starts_with("AT Z") || c.starts_with("AT WS")).collect();
+        assert_eq!(after, ["AT WS"]);
+    }
+}
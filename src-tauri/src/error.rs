@@ -1,7 +1,7 @@
 use crate::{elm327, kia};
 use serde::Serialize;
 
-#[derive(thiserror::Error, Serialize, Debug)]
+#[derive(thiserror::Error, Serialize, Debug, Clone)]
 #[error("Command error: {code} - {message}")]
 pub struct CommandError {
     pub code: String,
@@ -24,14 +24,60 @@ impl CommandError {
             parameters: None,
         }
     }
+    pub fn new_invalid_confirmation_token() -> Self {
+        Self {
+            code: "invalid_confirmation_token".to_string(),
+            message: "The confirmation expired, try again".to_string(),
+            parameters: None,
+        }
+    }
+    pub fn new_can_monitor_running() -> Self {
+        Self {
+            code: "can_monitor_running".to_string(),
+            message: "The CAN monitor is running".to_string(),
+            parameters: None,
+        }
+    }
+}
+
+impl From<&elm327::Error> for CommandError {
+    fn from(e: &elm327::Error) -> Self {
+        let (code, message) = match e {
+            elm327::Error::Communication | elm327::Error::Other => return CommandError::new_internal(),
+            elm327::Error::NotConnected => return CommandError::new_not_connected(),
+            elm327::Error::NoData => ("no_data", "The car didn't answer, is the ignition on?"),
+            elm327::Error::UnableToConnect => ("unable_to_connect", "Can't reach the car, is the ignition on?"),
+            elm327::Error::Searching => ("searching", "The adapter is still looking for the car's protocol"),
+            elm327::Error::Can => ("can_error", "CAN bus error, check that the adapter is plugged in firmly"),
+            elm327::Error::BusInit => ("bus_init_error", "The adapter couldn't initialize the bus"),
+            elm327::Error::BufferFull => ("buffer_full", "The adapter's buffer overflowed, the connection is too slow"),
+            elm327::Error::Stopped => ("stopped", "The adapter interrupted the request"),
+            elm327::Error::UnknownCommand => ("unknown_command", "The adapter doesn't support a required command"),
+            elm327::Error::RequestRejected => ("request_rejected", "The request would change the car's state, enable expert mode to send it"),
+            elm327::Error::ReplayMismatch => ("replay_mismatch", "Command is not in the replayed capture"),
+        };
+        CommandError {
+            code: code.to_string(),
+            message: message.to_string(),
+            parameters: None,
+        }
+    }
+}
+
+impl CommandError {
+    /// Same as `From<&elm327::Error>`, with what the replay didn't expect from further down `report`
+    fn from_adapter<C>(e: &elm327::Error, report: &error_stack::Report<C>) -> Self {
+        let mut error = CommandError::from(e);
+        if let Some(elm327::transport::Error::ReplayMismatch(message)) = report.downcast_ref::<elm327::transport::Error>() {
+            error.parameters = Some(vec![message.clone()]);
+        }
+        error
+    }
 }
 
 impl From<error_stack::Report<elm327::Error>> for CommandError {
     fn from(e: error_stack::Report<elm327::Error>) -> Self {
-        match e.current_context() {
-            elm327::Error::Communication | elm327::Error::Other => CommandError::new_internal(),
-            elm327::Error::NotConnected => CommandError::new_not_connected(),
-        }
+        CommandError::from_adapter(e.current_context(), &e)
     }
 }
 
@@ -39,11 +85,39 @@ impl From<error_stack::Report<kia::Error>> for CommandError {
     fn from(e: error_stack::Report<kia::Error>) -> Self {
         match e.current_context() {
             kia::Error::NotConnected => CommandError::new_not_connected(),
+            kia::Error::Adapter(error) => CommandError::from_adapter(error, &e),
+            kia::Error::UnsupportedAdapter(command) => CommandError {
+                code: "unsupported_adapter".to_string(),
+                message: "The adapter doesn't support a command the car needs".to_string(),
+                parameters: Some(vec![command.clone()]),
+            },
+            kia::Error::LowVoltage(voltage) => CommandError {
+                code: "low_voltage".to_string(),
+                message: "The 12V battery is low, the car is read less often to protect it".to_string(),
+                parameters: Some(vec![format!("{:.1}", voltage)]),
+            },
             kia::Error::Other => CommandError::new_internal(),
         }
     }
 }
 
+impl From<error_stack::Report<kia::signals::Error>> for CommandError {
+    fn from(e: error_stack::Report<kia::signals::Error>) -> Self {
+        match e.current_context() {
+            kia::signals::Error::Read(path) => CommandError {
+                code: "signal_definitions_unreadable".to_string(),
+                message: "Can't read the signal definitions".to_string(),
+                parameters: Some(vec![path.clone()]),
+            },
+            kia::signals::Error::Invalid(source, message) => CommandError {
+                code: "invalid_signal_definitions".to_string(),
+                message: "Bad signal definitions".to_string(),
+                parameters: Some(vec![source.clone(), message.clone()]),
+            },
+        }
+    }
+}
+
 impl From<error_stack::Report<elm327::transport::Error>> for CommandError {
     fn from(e: error_stack::Report<elm327::transport::Error>) -> Self {
         match e.current_context() {
@@ -54,6 +128,11 @@ impl From<error_stack::Report<elm327::transport::Error>> for CommandError {
             },
             elm327::transport::Error::NotConnected => CommandError::new_not_connected(),
             elm327::transport::Error::IO(_) | elm327::transport::Error::Other => CommandError::new_internal(),
+            elm327::transport::Error::ReplayMismatch(msg) => CommandError {
+                code: "replay_mismatch".to_string(),
+                message: "Command is not in the replayed capture".to_string(),
+                parameters: Some(vec![msg.clone()]),
+            },
             elm327::transport::Error::InvalidParameter(param, msg) => CommandError {
                 code: "bad_parameter".to_string(),
                 message: "Bad parameter".to_string(),
@@ -61,4 +140,37 @@ impl From<error_stack::Report<elm327::transport::Error>> for CommandError {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::capture::{CaptureSink, MemoryCapture, NoCapture};
+    use crate::elm327::transport::{Replay, ReplayMode};
+    use crate::elm327::Elm327;
+    use crate::kia::simulator::{PackState, Simulator};
+
+    /// Capture of an adapter setup, as `FileCapture` writes it
+    fn setup_capture() -> String {
+        let capture = MemoryCapture::default();
+        Elm327::new(Box::new(Simulator::new(PackState::default())), Box::new(capture.clone())).unwrap();
+        capture
+            .records()
+            .iter()
+            .map(|record| format!("{}\n", serde_json::to_string(record).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn strict_replay_mismatch() {
+        let replay = Replay::from_log(&setup_capture(), ReplayMode::Strict).unwrap();
+        let mut elm327 = Elm327::new(Box::new(replay), Box::new(NoCapture)).unwrap();
+
+        let e = elm327.serial_cmd("21 01").unwrap_err();
+        assert_eq!(*e.current_context(), elm327::Error::ReplayMismatch);
+        let error = CommandError::from(e);
+        assert_eq!(error.code, "replay_mismatch");
+        let parameters = error.parameters.unwrap();
+        assert!(parameters[0].contains("21 01"), "{:?}", parameters);
+    }
+}
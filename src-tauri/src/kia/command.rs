@@ -1,139 +1,237 @@
-use std::collections::HashMap;
-use error_stack::{Report, ResultExt};
-use crate::elm327;
-use crate::elm327::Command;
-use crate::kia::{CellVoltages, Error};
+use serde::Serialize;
+use crate::kia::CumulativeCounters;
+use crate::kia::signals::{signal_number, SignalValue};
 
 // Commands information from https://github.com/langemand/SoulEVSpy/blob/master/app/src/main/java/com/evranger/soulevspy/util/BatteryManagementSystemParser.java
 
-pub struct CellVoltagesCommand(pub String);
-
-impl Command for CellVoltagesCommand {
-    type Response = [f32; 35];
-    fn serial_command(&self) -> String {
-        return format!("21 0{}", self.0).to_string();
-    }
-    fn parse_result(&self, response: String) -> elm327::error::Result<Self::Response> {
-        let frames = parse_multi_frame_response::<7>(response)?;
-        let mut result = [0.0; 35];
-
-        let frame_offsets = [("21", 0), ("22", 7), ("23", 14), ("24", 21), ("25", 28)];
-
-        for (frame, offset) in frame_offsets {
-            let values = frames.get(frame).ok_or(
-                Report::new(elm327::Error::Other)
-                    .attach_printable(format!("missing block {}", frame))
-            )?;
-            result[offset..offset + 7].clone_from_slice(&values.map(|v| (v as f32) * 0.02))
-        }
-
-        return Ok(result);
-    }
-}
-
 #[derive(Default)]
 pub struct BatteryInfo {
     pub charge_level: f64,
+    /// kW the BMS accepts while charging or regenerating
+    pub available_charge_power: f64,
+    /// kW the BMS lets the motor draw
+    pub available_discharge_power: f64,
     pub charging: bool,
     pub chademo_plugged: bool,
     pub j1772_plugged: bool,
+    /// Positive while discharging, A
     pub battery_current: f64,
     pub battery_dc_voltage: f64,
     pub max_cell_voltage: f64,
+    /// Cell numbers start at 1
+    pub max_cell_number: u8,
     pub min_cell_voltage: f64,
+    pub min_cell_number: u8,
+    /// rpm, negative when reversing
     pub motor_speed: i32,
-    pub module_temperatures: [i32; 7],
+    pub module_temperatures: Vec<i32>,
+    pub fan_mode: u8,
+    /// Hz
+    pub fan_speed: u8,
+    /// 12V supply as the BMS measures it
+    pub aux_battery_voltage: f64,
+    /// kΩ between the pack and the chassis, `None` when the response ends before it
+    pub isolation_resistance: Option<u16>,
+    /// `None` when the response ends before them
+    pub counters: Option<CumulativeCounters>,
+    /// Signals with a name that isn't one of the above
+    pub other: Vec<SignalValue>,
 }
 
-pub struct BatteryInfoCommand();
-
-impl Command for BatteryInfoCommand {
-    type Response = BatteryInfo;
+/// Module temperature signals are named by it and the module number, starting at 1
+pub const MODULE_TEMPERATURE: &str = "module_temperature_";
+/// Cell voltage signals are named by it and the cell number, starting at 1
+pub const CELL_VOLTAGE: &str = "cell_voltage_";
+/// Battery heater temperature signals are named by it and the heater number, starting at 1
+const HEATER_TEMPERATURE: &str = "heater_temperature_";
+
+/// Values of the signals named `prefix` and a number, in number order
+pub fn numbered_values(values: &[SignalValue], prefix: &str) -> Vec<f64> {
+    let mut numbered: Vec<(usize, f64)> = values
+        .iter()
+        .filter_map(|signal| signal_number(&signal.name, prefix).map(|number| (number, signal.value)))
+        .collect();
+    numbered.sort_by_key(|(number, _)| *number);
+    numbered.into_iter().map(|(_, value)| value).collect()
+}
 
-    fn serial_command(&self) -> String {
-        return "21 01".to_string();
+impl BatteryInfo {
+    /// Picks the values this app knows by their signal name, the rest are passed on as they are
+    pub fn from_signals(values: Vec<SignalValue>) -> Self {
+        let mut result = BatteryInfo::default();
+        let mut module_temperatures = Vec::new();
+        let mut counters = [None; 5];
+        for signal in values {
+            let value = signal.value;
+            match signal.name.as_str() {
+                "charge_level" => result.charge_level = value,
+                "available_charge_power" => result.available_charge_power = value,
+                "available_discharge_power" => result.available_discharge_power = value,
+                "charging" => result.charging = value != 0.0,
+                "chademo_plugged" => result.chademo_plugged = value != 0.0,
+                "j1772_plugged" => result.j1772_plugged = value != 0.0,
+                "battery_current" => result.battery_current = value,
+                "battery_dc_voltage" => result.battery_dc_voltage = value,
+                "max_cell_voltage" => result.max_cell_voltage = value,
+                "max_cell_number" => result.max_cell_number = value as u8,
+                "min_cell_voltage" => result.min_cell_voltage = value,
+                "min_cell_number" => result.min_cell_number = value as u8,
+                "motor_speed" => result.motor_speed = value as i32,
+                "fan_mode" => result.fan_mode = value as u8,
+                "fan_speed" => result.fan_speed = value as u8,
+                "aux_battery_voltage" => result.aux_battery_voltage = value,
+                "isolation_resistance" => result.isolation_resistance = Some(value as u16),
+                "cumulative_charge_current" => counters[0] = Some(value),
+                "cumulative_discharge_current" => counters[1] = Some(value),
+                "cumulative_energy_charged" => counters[2] = Some(value),
+                "cumulative_energy_discharged" => counters[3] = Some(value),
+                "operating_time" => counters[4] = Some(value),
+                name => match signal_number(name, MODULE_TEMPERATURE) {
+                    Some(module) => module_temperatures.push((module, value as i32)),
+                    None => result.other.push(signal),
+                },
+            }
+        }
+        module_temperatures.sort_by_key(|(module, _)| *module);
+        result.module_temperatures = module_temperatures.into_iter().map(|(_, temperature)| temperature).collect();
+        if let [Some(charge_current), Some(discharge_current), Some(energy_charged), Some(energy_discharged), Some(operating_time)] = counters {
+            result.counters = Some(CumulativeCounters {
+                charge_current,
+                discharge_current,
+                energy_charged,
+                energy_discharged,
+                operating_time: operating_time as u32,
+            });
+        }
+        result
     }
-    fn parse_result(&self, response: String) -> elm327::error::Result<Self::Response> {
-        let frames = parse_multi_frame_response::<7>(response)?;
-        let frame_21 = frames.get("21").ok_or(Report::new(elm327::Error::Other).attach_printable("missing frame 21".to_string()))?;
-        let frame_22 = frames.get("22").ok_or(Report::new(elm327::Error::Other).attach_printable("missing frame 22".to_string()))?;
-        let frame_23 = frames.get("23").ok_or(Report::new(elm327::Error::Other).attach_printable("missing frame 23".to_string()))?;
-        let frame_24 = frames.get("24").ok_or(Report::new(elm327::Error::Other).attach_printable("missing frame 24".to_string()))?;
+}
 
-        let mut result = BatteryInfo::default();
+#[derive(Serialize, Default, Clone)]
+pub struct BatteryHealth {
+    /// %
+    pub state_of_health: f64,
+    /// Capacity lost by the worst cell, %
+    pub max_deterioration: f64,
+    /// Cell numbers start at 1
+    pub max_deterioration_cell: u8,
+    pub min_deterioration: f64,
+    pub min_deterioration_cell: u8,
+    /// `None` when the BMS reports it elsewhere or not at all
+    pub inlet_temperature: Option<i32>,
+    pub heater_temperatures: Option<[i32; 2]>,
+    /// Signals from the definition file the app has no field for
+    pub other_signals: Vec<SignalValue>,
+}
 
-        let charging_flags = frame_21[5];
-        result.charge_level = (frame_21[0] as f64) * 0.5;
-        result.charging = (charging_flags & (1 << 7)) != 0;
-        result.chademo_plugged = (charging_flags & (1 << 6)) != 0;
-        result.j1772_plugged = (charging_flags & (1 << 5)) != 0;
-        let (battery_dc_voltage, _) = frame_22[1].overflowing_shl(8);
-        result.battery_dc_voltage = (battery_dc_voltage + frame_22[2]) as f64 * 0.1;
-        result.min_cell_voltage = frame_23[0] as f64 * 0.02;
-        result.max_cell_voltage = frame_24[0] as f64 * 0.02;
-        result.module_temperatures = [
-            frame_22[3],
-            frame_22[4],
-            frame_22[5],
-            frame_22[6],
-            frame_23[0],
-            frame_23[1],
-            frame_23[2],
-        ];
-
-        let msb = frame_21[6];
-        if msb > 0 {
-            result.battery_current = (msb.overflowing_shl(8).0 + frame_22[0]) as f64 * 0.1;
-            if (msb & 0x80) != 0 {
-                result.battery_current -= 6553.6;
+impl BatteryHealth {
+    /// Picks the values this app knows by their signal name, the rest are passed on as they are
+    pub fn from_signals(values: Vec<SignalValue>) -> Self {
+        let mut result = BatteryHealth::default();
+        let mut heater_temperatures = Vec::new();
+        for signal in values {
+            let value = signal.value;
+            match signal.name.as_str() {
+                "state_of_health" => result.state_of_health = value,
+                "max_deterioration" => result.max_deterioration = value,
+                "max_deterioration_cell" => result.max_deterioration_cell = value as u8,
+                "min_deterioration" => result.min_deterioration = value,
+                "min_deterioration_cell" => result.min_deterioration_cell = value as u8,
+                "inlet_temperature" => result.inlet_temperature = Some(value as i32),
+                name => match signal_number(name, HEATER_TEMPERATURE) {
+                    Some(heater) => heater_temperatures.push((heater, value as i32)),
+                    None => result.other_signals.push(signal),
+                },
             }
         }
-
-        Ok(result)
+        heater_temperatures.sort_by_key(|(heater, _)| *heater);
+        if let [(_, first), (_, second)] = heater_temperatures[..] {
+            result.heater_temperatures = Some([first, second]);
+        }
+        result
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::Command;
+    use crate::kia::BMS;
+    use crate::kia::profile::{SoulEv, VehicleProfile};
+    use crate::kia::signals::SignalSet;
+
+    /// `21 01` answer of a Soul EV BMS as the adapter prints it with headers on. Every field has
+    /// a value no other field has, so a wrong offset doesn't go unnoticed.
+    const BATTERY_INFO: &str = "\
+7EC 10 3E 61 01 FF FF FF FF
+7EC 21 BC 26 48 27 10 A0 FF
+7EC 22 85 0E 69 10 FE 0E 0F
+7EC 23 0D 10 FE 0F 0E 00 CA
+7EC 24 2A C8 55 01 1E 8F 00
+7EC 25 00 6B A4 00 00 6D F2
+7EC 26 00 00 26 92 00 00 27
+7EC 27 AA 00 51 B4 E3 00 09
+7EC 28 00 FB 2E 00 00 03 E8
+";
+
+    fn battery_info(response: &str) -> BatteryInfo {
+        let signals = SignalSet::parse(SoulEv.id(), SoulEv.signals()).unwrap();
+        let values = signals.command(&BMS, "21 01", |_| true).parse_result(response.to_string()).unwrap();
+        BatteryInfo::from_signals(values)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
 
-// returns a map of block number to array of 7 integers
-// example:
-//  response:
-//    7E8 10 21 00 00 00 00 F2 00\n
-//    7E8 21 00 00 00 00 00 00 00\n
-//  output:
-//    {
-//      "10": [33, 0, 0, 0, 0, 242, 0],
-//      "21": [0, 0, 0, 0, 0, 0, 0],
-//    }
-
-fn parse_multi_frame_response<const N: usize>(response: String) -> elm327::Result<HashMap<String, [i32; N]>> {
-    response
-        .split("\n")
-        .filter(|l| !l.trim().is_empty())
-        .map(|l| {
-            let mut parts = l.trim().split(" ").skip(1);
-            let block = parts
-                .next()
-                .ok_or(Report::new(elm327::Error::Other)
-                    .attach_printable(format!("Line does not contain block number: {}", l))
-                )?;
-            let int_result_parts = parts
-                .map(|s| {
-                    i32::from_str_radix(s, 16)
-                        .attach_printable(format!("can't parse int32 from {s}"))
-                        .change_context(elm327::Error::Other)
-                })
-                .collect::<elm327::Result<Vec<i32>>>()?;
-
-            if int_result_parts.len() < N {
-                return Err(Report::new(elm327::Error::Other)
-                    .attach_printable(
-                        format!("response {} has {} elements, when {} elements required", block, int_result_parts.len(), N)
-                    ));
-            }
-            let mut result = [0; N];
-            result.clone_from_slice(&int_result_parts.as_slice());
+    #[test]
+    fn battery_info_block() {
+        let info = battery_info(BATTERY_INFO);
+
+        assert_close(info.charge_level, 94.0);
+        assert_close(info.available_charge_power, 98.0);
+        assert_close(info.available_discharge_power, 100.0);
+        assert!(info.charging);
+        assert!(!info.chademo_plugged);
+        assert!(info.j1772_plugged);
+        assert_close(info.battery_current, -12.3);
+        assert_close(info.battery_dc_voltage, 368.9);
+        assert_eq!(info.module_temperatures, vec![14, 15, 13, 16, -2, 15, 14]);
+        assert_close(info.max_cell_voltage, 4.04);
+        assert_eq!(info.max_cell_number, 42);
+        assert_close(info.min_cell_voltage, 4.0);
+        assert_eq!(info.min_cell_number, 85);
+        assert_eq!(info.fan_mode, 1);
+        assert_eq!(info.fan_speed, 30);
+        assert_close(info.aux_battery_voltage, 14.3);
+
+        let counters = info.counters.unwrap();
+        assert_close(counters.charge_current, 2755.6);
+        assert_close(counters.discharge_current, 2814.6);
+        assert_close(counters.energy_charged, 987.4);
+        assert_close(counters.energy_discharged, 1015.4);
+        assert_eq!(counters.operating_time, 5354723);
+
+        assert_eq!(info.motor_speed, -1234);
+        assert_eq!(info.isolation_resistance, Some(1000));
+        assert!(info.other.is_empty(), "{:?}", info.other);
+    }
 
-            Ok((block.to_string(), result))
-        })
-        .collect::<elm327::Result<HashMap<String, [i32; N]>>>()
-}
\ No newline at end of file
+    #[test]
+    fn battery_info_block_without_counters() {
+        // a block that ends after the aux battery voltage, the optional values are left out
+        let response = "\
+7EC 10 21 61 01 FF FF FF FF
+7EC 21 BC 26 48 27 10 A0 FF
+7EC 22 85 0E 69 10 FE 0E 0F
+7EC 23 0D 10 FE 0F 0E 00 CA
+7EC 24 2A C8 55 01 1E 8F 00
+";
+        let info = battery_info(response);
+
+        assert_close(info.aux_battery_voltage, 14.3);
+        assert_eq!(info.counters, None);
+        assert_eq!(info.motor_speed, 0);
+        assert_eq!(info.isolation_resistance, None);
+    }
+}
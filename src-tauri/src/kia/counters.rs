@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+/// Lifetime counters the BMS keeps, they only go up unless the BMS is replaced
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct CumulativeCounters {
+    /// Ah
+    pub charge_current: f64,
+    /// Ah
+    pub discharge_current: f64,
+    /// kWh
+    pub energy_charged: f64,
+    /// kWh
+    pub energy_discharged: f64,
+    /// Seconds the BMS has been running
+    pub operating_time: u32,
+}
+
+/// What went through the pack in both directions
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    /// Ah
+    pub charge: f64,
+    /// kWh
+    pub energy: f64,
+}
+
+impl CumulativeCounters {
+    pub fn throughput(&self) -> Throughput {
+        Throughput {
+            charge: self.charge_current + self.discharge_current,
+            energy: self.energy_charged + self.energy_discharged,
+        }
+    }
+
+    /// What changed between `earlier` and this reading, `None` when a counter went backwards,
+    /// e.g. the readings come from different cars or a replaced BMS
+    pub fn since(&self, earlier: &Self) -> Option<Self> {
+        let delta = Self {
+            charge_current: self.charge_current - earlier.charge_current,
+            discharge_current: self.discharge_current - earlier.discharge_current,
+            energy_charged: self.energy_charged - earlier.energy_charged,
+            energy_discharged: self.energy_discharged - earlier.energy_discharged,
+            operating_time: self.operating_time.checked_sub(earlier.operating_time)?,
+        };
+        let went_back = [delta.charge_current, delta.discharge_current, delta.energy_charged, delta.energy_discharged]
+            .iter()
+            .any(|value| *value < 0.0);
+        (!went_back).then_some(delta)
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use super::SignalGroup;
+
+/// Slows polling down while the 12V battery is low. Every request keeps the car's CAN bus awake,
+/// long sessions with the car parked have drained the battery.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LowVoltageSettings {
+    pub enabled: bool,
+    /// Volts. The DC-DC converter holds the rail above 13V while the car is on or charging, so a
+    /// lower reading means the car is off.
+    pub threshold: f64,
+    /// Seconds between reads of each signal group while the voltage is below the threshold
+    pub interval: u64,
+}
+
+impl Default for LowVoltageSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 12.2,
+            interval: 60,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct LowVoltageGuard {
+    pub settings: LowVoltageSettings,
+    last_reads: HashMap<SignalGroup, Instant>,
+    charging: bool,
+}
+
+impl LowVoltageGuard {
+    /// Whether to skip reading `group` this time
+    pub fn should_skip(&self, group: SignalGroup, voltage: f64) -> bool {
+        let low = self.settings.enabled && voltage < self.settings.threshold;
+        let recently_read = self
+            .last_reads
+            .get(&group)
+            .is_some_and(|last_read| last_read.elapsed() < Duration::from_secs(self.settings.interval));
+        // charging keeps the car awake anyway
+        low && !self.charging && recently_read
+    }
+
+    pub fn record_read(&mut self, group: SignalGroup) {
+        self.last_reads.insert(group, Instant::now());
+    }
+
+    pub fn set_charging(&mut self, charging: bool) {
+        self.charging = charging;
+    }
+}
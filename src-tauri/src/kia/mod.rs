@@ -1,69 +1,153 @@
 mod command;
+mod counters;
+mod low_voltage;
+pub mod profile;
+pub mod signals;
+pub mod simulator;
 
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use error_stack::{Report, ResultExt};
-use serde::{Serialize, Serializer};
-use serde::ser::{SerializeSeq};
+use serde::{Deserialize, Serialize, Serializer};
 use crate::elm327::Command;
+use crate::elm327::monitor::{CanFrame, MonitorSettings};
+use crate::obd2::mode01::{self, PidValue};
+use crate::obd2::mode09;
+use crate::obd2::dtc::{self, Dtc, DtcKind};
+use crate::uds;
+use low_voltage::LowVoltageGuard;
+pub use low_voltage::LowVoltageSettings;
+pub use counters::{CumulativeCounters, Throughput};
+use profile::VehicleProfile;
+use signals::{SignalDefinition, SignalSet, SignalValue};
+use log::{debug, warn};
 
 type Result<T> = error_stack::Result<T, Error>;
 
 use super::{elm327::{
-    Elm327
+    Elm327, EcuAddress
 }, elm327};
 
 #[derive(Debug,thiserror::Error)]
 pub enum Error {
     #[error("Not connected")]
     NotConnected,
+    #[error("Adapter error: {0}")]
+    Adapter(elm327::Error),
+    #[error("Adapter doesn't support {0}")]
+    UnsupportedAdapter(String),
+    #[error("12V battery low ({0:.1}V), reading the car less often")]
+    LowVoltage(f64),
     #[error("Internal error")]
     Other,
 }
 impl Error {
     fn from_elm327(e: Report<elm327::Error>) -> Report<Error> {
-        match e.current_context() {
+        match *e.current_context() {
             elm327::Error::Communication => e.change_context(Error::Other),
             elm327::Error::Other => e.change_context(Error::Other),
             elm327::Error::NotConnected => e.change_context(Error::NotConnected),
+            error => e.change_context(Error::Adapter(error)),
+        }
+    }
+
+    fn from_uds(e: Report<uds::Error>) -> Report<Error> {
+        match *e.current_context() {
+            uds::Error::Adapter(elm327::Error::NotConnected) => e.change_context(Error::NotConnected),
+            uds::Error::Adapter(elm327::Error::Communication | elm327::Error::Other) => e.change_context(Error::Other),
+            uds::Error::Adapter(error) => e.change_context(Error::Adapter(error)),
+            _ => e.change_context(Error::Other),
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub struct DiagnosticEcu {
+    name: &'static str,
+    address: EcuAddress,
+}
+
+const BMS: DiagnosticEcu = DiagnosticEcu { name: "BMS", address: EcuAddress::standard(0x7E4, 0x7EC) };
+const VMCU: DiagnosticEcu = DiagnosticEcu { name: "VMCU", address: EcuAddress::standard(0x7E2, 0x7EA) };
+
 pub(crate) struct Kia {
     device: Elm327,
+    profile: Box<dyn VehicleProfile>,
+    signals: SignalSet,
+    /// Mode 01 PIDs the car supports, queried on first use
+    obd2_pids: Option<Vec<u8>>,
+    /// Diagnostic session open on an ECU, kept alive by `keep_alive`
+    session: Option<(DiagnosticEcu, uds::Session)>,
+    low_voltage: LowVoltageGuard,
+    /// Counters at the first pack reading of the connection
+    first_counters: Option<CumulativeCounters>,
 }
 
-struct CellVoltages([f32; 96]);
-impl Serialize for CellVoltages {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-    {
-        let mut state = serializer.serialize_seq(Some(96))?;
-        for i in 0..96 {
-            state.serialize_element(&self.0[i])?;
-        }
-        state.end()
-    }
+#[derive(Serialize, Clone)]
+pub struct CellVoltages(Vec<f32>);
+
+/// Parts of the BMS data that are read on their own schedule
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalGroup {
+    /// Current, voltage, charge level and charging state
+    Pack,
+    CellVoltages,
+    Temperatures,
+    /// State of health and deterioration, they change over months
+    Health,
 }
 
-#[derive(Serialize)]
-pub struct BatteryInfo {
+#[derive(Serialize, Clone)]
+pub struct PackValues {
     charge_level: f64,
+    available_charge_power: f64,
+    available_discharge_power: f64,
     charging: bool,
     chademo_plugged: bool,
     j1772_plugged: bool,
     battery_current: f64,
     battery_dc_voltage: f64,
     max_cell_voltage: f64,
+    max_cell_number: u8,
     min_cell_voltage: f64,
+    min_cell_number: u8,
     motor_speed: i32,
-    cell_voltages: CellVoltages,
-    module_temperatures: [i32; 7],
+    fan_mode: u8,
+    fan_speed: u8,
+    isolation_resistance: Option<u16>,
+    /// 12V battery as the adapter measures it, the BMS reading when the adapter can't
+    aux_battery_voltage: Option<f64>,
+    counters: Option<CumulativeCounters>,
+    lifetime_throughput: Option<Throughput>,
+    since_connected: Option<CumulativeCounters>,
+    /// Signals from the definition file the app has no field for
+    other_signals: Vec<SignalValue>,
 }
 
-struct CarInfoTime(std::time::SystemTime);
-impl Serialize for CarInfoTime {
+#[derive(Serialize, Clone)]
+#[serde(tag = "group", content = "values", rename_all = "snake_case")]
+pub enum SignalValues {
+    Pack(PackValues),
+    CellVoltages(CellVoltages),
+    Temperatures(Vec<i32>),
+    Health(command::BatteryHealth),
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct VehicleIdentity {
+    vin: Option<String>,
+    model_year: Option<u16>,
+    calibration_ids: Vec<String>,
+    ecu_names: Vec<String>,
+    bms_part_number: Option<String>,
+    bms_software_version: Option<String>,
+}
+
+#[derive(Clone)]
+struct SampleTime(std::time::SystemTime);
+impl Serialize for SampleTime {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
         where
             S: Serializer,
@@ -72,18 +156,43 @@ impl Serialize for CarInfoTime {
     }
 }
 
-#[derive(Serialize)]
-pub struct CarInfo {
-    time: CarInfoTime,
-    battery_info: BatteryInfo,
+/// One reading of a signal group
+#[derive(Serialize, Clone)]
+pub struct Sample {
+    time: SampleTime,
+    #[serde(flatten)]
+    values: SignalValues,
 }
 
 impl Kia {
-    pub fn new(device: Elm327) -> Self {
-        Self { device }
+    pub fn new(device: Elm327, profile: Box<dyn VehicleProfile>, signals: SignalSet) -> Self {
+        Self { device, profile, signals, obd2_pids: None, session: None, low_voltage: LowVoltageGuard::default(), first_counters: None }
+    }
+
+    /// Closes the connection from another thread once set
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.device.cancel_handle()
+    }
+
+    /// Expert mode lets requests through that change the state of the car
+    pub fn set_expert_mode(&mut self, enabled: bool) {
+        self.device.set_write_access(enabled);
+    }
+
+    pub fn set_low_voltage_settings(&mut self, settings: LowVoltageSettings) {
+        self.low_voltage.settings = settings;
     }
 
     pub fn init(&mut self) -> Result<()> {
+        // the ECU falls back to the default session on its own once we stop talking to it
+        self.session = None;
+        let capabilities = self.device.get_capabilities().clone();
+        // the BMS answers in multi-frame messages that only CAN auto formatting puts together
+        if !capabilities.can_auto_format {
+            return Err(Report::new(Error::UnsupportedAdapter("AT CAF1".to_string()))
+                .attach_printable(format!("adapter {}", capabilities.identifier)));
+        }
+
         let commands = [
             "AT D",
             "AT Z",
@@ -96,62 +205,441 @@ impl Kia {
             "AT AL",
             "AT S1",
             "AT FE",
-            "AT SPA6",
             "AT CAF1",
-            "01 00",
-            "01 20",
-            "09 00",
-            "01 01",
-            "AT SH 7DF",
-            "AT CRA 7EC",
-            "21 00",
         ];
 
-        for cmd in commands {
-            self.device.serial_cmd(cmd).map_err(Error::from_elm327)?;
+        for cmd in commands.iter().chain(self.profile.init_commands()) {
+            match self.device.serial_cmd(cmd) {
+                // clones don't implement every setting, none of them is essential
+                Err(e) if cmd.starts_with("AT") && *e.current_context() == elm327::Error::UnknownCommand => {
+                    warn!("Adapter doesn't support {}", cmd);
+                }
+                // only the first OBD request has to be answered, that's how we know the car is on
+                Err(e) if *cmd != "01 00" && *e.current_context() == elm327::Error::NoData => {
+                    debug!("No answer to {}", cmd);
+                }
+                result => {
+                    result.map_err(Error::from_elm327)?;
+                }
+            }
+        }
+        self.set_address(&self.profile.bms().address)?;
+        for cmd in self.profile.bms_init_commands() {
+            match self.device.serial_cmd(cmd) {
+                Err(e) if *e.current_context() == elm327::Error::NoData => debug!("No answer to {}", cmd),
+                result => {
+                    result.map_err(Error::from_elm327)?;
+                }
+            }
         }
 
         return Ok(());
     }
 
-    pub fn get_cell_voltages(&mut self) -> Result<[f32; 96]> {
-        let mut result: [f32; 96] = [0.0; 96];
+    fn set_address(&mut self, address: &EcuAddress) -> Result<()> {
+        self.device.set_address(address).map_err(Error::from_elm327)
+    }
 
-        for i in 0..3 {
-            let response = self.device
-                .execute_command(command::CellVoltagesCommand(format!("{}", i+2)))
-                .map_err(Error::from_elm327)?;
-            result[i * 32..(i + 1) * 32].copy_from_slice(&response[..32]);
+    /// Stored, pending and permanent OBD codes plus the fault memory of the EV ECUs
+    pub fn get_dtcs(&mut self) -> Result<Vec<Dtc>> {
+        self.set_address(&EcuAddress::OBD_BROADCAST)?;
+        let mut dtcs = Vec::new();
+        for kind in [DtcKind::Stored, DtcKind::Pending, DtcKind::Permanent] {
+            dtcs.extend(Self::optional(self.device.execute_command(dtc::ReadDtcsCommand(kind)))?.unwrap_or_default());
+        }
+
+        for &ecu in self.profile.diagnostic_ecus() {
+            let request = dtc::ReportDtcsByStatusMask { mask: 0xFF, ecu: ecu.name };
+            dtcs.extend(self.uds_request(ecu, &request)?.unwrap_or_default());
         }
+        Ok(dtcs)
+    }
 
-        return Ok(result);
+    /// Clears the OBD codes and the EV ECUs' fault memory, whether or not expert mode is on
+    pub fn clear_dtcs(&mut self) -> Result<()> {
+        let write_access = self.device.has_write_access();
+        self.device.set_write_access(true);
+        let result = self.clear_all_dtcs();
+        self.device.set_write_access(write_access);
+        result
     }
 
-    fn get_battery_info(&mut self) -> Result<BatteryInfo> {
-        let battery_info = self.device
-            .execute_command(command::BatteryInfoCommand())
+    fn clear_all_dtcs(&mut self) -> Result<()> {
+        self.device
+            .execute_command_on(&EcuAddress::OBD_BROADCAST, dtc::ClearDtcsCommand())
             .map_err(Error::from_elm327)?;
+        for &ecu in self.profile.diagnostic_ecus() {
+            // some ECUs only clear their memory in the extended session, the rest do it in either
+            if !self.open_session(ecu, uds::SessionType::Extended)? {
+                debug!("{} stays in the default session", ecu.name);
+            }
+            let cleared = self.uds_request(ecu, &dtc::ClearDiagnosticInformation());
+            self.close_session()?;
+            if cleared?.is_none() {
+                warn!("{} didn't clear its fault memory", ecu.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a diagnostic session on `ecu`, closing the one open elsewhere. `false` when the ECU
+    /// refuses it.
+    fn open_session(&mut self, ecu: DiagnosticEcu, kind: uds::SessionType) -> Result<bool> {
+        if self.session.as_ref().is_some_and(|(open, session)| *open == ecu && session.kind() == kind) {
+            return Ok(true);
+        }
+        self.close_session()?;
+        self.set_address(&ecu.address)?;
+        let session = Self::optional_uds(uds::Session::open(&mut self.device, kind))?;
+        let opened = session.is_some();
+        self.session = session.map(|session| (ecu, session));
+        Ok(opened)
+    }
 
-        return Ok(BatteryInfo{
-            charge_level: battery_info.charge_level,
-            charging: battery_info.charging,
-            chademo_plugged: battery_info.chademo_plugged,
-            j1772_plugged: battery_info.j1772_plugged,
-            battery_current: battery_info.battery_current,
-            battery_dc_voltage: battery_info.battery_dc_voltage,
-            max_cell_voltage: battery_info.max_cell_voltage,
-            min_cell_voltage: battery_info.min_cell_voltage,
-            motor_speed: battery_info.motor_speed,
-            cell_voltages: CellVoltages(self.get_cell_voltages()?),
-            module_temperatures: battery_info.module_temperatures,
-        });
-    }
-
-    pub fn get_car_info(&mut self) -> Result<CarInfo> {
-        Ok(CarInfo {
-            time: CarInfoTime(std::time::SystemTime::now()),
-            battery_info: self.get_battery_info()?,
+    fn close_session(&mut self) -> Result<()> {
+        if let Some((ecu, session)) = self.session.take() {
+            self.set_address(&ecu.address)?;
+            Self::optional_uds(session.close(&mut self.device))?;
+        }
+        Ok(())
+    }
+
+    /// Sends tester present when the open diagnostic session has been idle for a while. Has to
+    /// be called every second or so while connected.
+    pub fn keep_alive(&mut self) -> Result<()> {
+        let Some((ecu, session)) = self.session.as_ref() else {
+            return Ok(());
+        };
+        if !session.is_idle() {
+            return Ok(());
+        }
+        let ecu = *ecu;
+        self.set_address(&ecu.address)?;
+        let alive = match self.session.as_mut() {
+            Some((_, session)) => Self::optional_uds(session.keep_alive(&mut self.device))?,
+            None => None,
+        };
+        if alive.is_none() {
+            warn!("{} left the diagnostic session", ecu.name);
+            self.session = None;
+        }
+        Ok(())
+    }
+
+    /// Sends a UDS request to `ecu`, within its diagnostic session when one is open. `None` when
+    /// the ECU rejects it.
+    fn uds_request<R: uds::Request>(&mut self, ecu: DiagnosticEcu, request: &R) -> Result<Option<R::Response>> {
+        self.set_address(&ecu.address)?;
+        let result = match self.session.as_mut() {
+            Some((open, session)) if *open == ecu => session.execute(&mut self.device, request),
+            _ => uds::execute(&mut self.device, request),
+        };
+        Self::optional_uds(result)
+    }
+
+    /// Whatever the car tells about itself, identifiers it doesn't answer stay empty
+    pub fn get_identity(&mut self) -> Result<VehicleIdentity> {
+        let mut identity = VehicleIdentity::default();
+
+        // Mode 09 is answered by the VMCU
+        self.set_address(&EcuAddress::OBD_BROADCAST)?;
+        self.read_obd2_identity(&mut identity)?;
+        self.read_bms_identity(&mut identity)?;
+
+        identity.model_year = identity.vin.as_deref().and_then(mode09::model_year);
+        Ok(identity)
+    }
+
+    fn read_obd2_identity(&mut self, identity: &mut VehicleIdentity) -> Result<()> {
+        identity.vin = Self::optional(self.device.execute_command(mode09::VinCommand()))?;
+        identity.calibration_ids = Self::optional(self.device.execute_command(mode09::CalibrationIdsCommand()))?
+            .unwrap_or_default();
+        identity.ecu_names = Self::optional(self.device.execute_command(mode09::EcuNamesCommand()))?
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    fn read_bms_identity(&mut self, identity: &mut VehicleIdentity) -> Result<()> {
+        let mut read = |did: u16| -> Result<Option<String>> {
+            let data = self.uds_request(self.profile.bms(), &uds::ReadDataByIdentifier(did))?;
+            Ok(data.map(|d| String::from_utf8_lossy(&d).trim_matches(|c: char| c.is_whitespace() || c == '\0').to_string()))
+        };
+        identity.bms_part_number = read(0xF187)?;
+        identity.bms_software_version = read(0xF189)?;
+        if identity.vin.is_none() {
+            identity.vin = read(0xF190)?.filter(|vin| vin.len() == 17);
+        }
+        Ok(())
+    }
+
+    /// `None` when the car didn't give a usable answer, failures to talk to the adapter and
+    /// requests the guard refused stay errors
+    fn optional<T>(result: elm327::Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if matches!(
+                e.current_context(),
+                elm327::Error::NotConnected | elm327::Error::Communication | elm327::Error::RequestRejected | elm327::Error::ReplayMismatch
+            ) => {
+                Err(Error::from_elm327(e))
+            }
+            Err(e) => {
+                debug!("No usable answer: {:?}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Same as `optional` for UDS requests, negative responses are logged with their reason
+    fn optional_uds<T>(result: uds::Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if matches!(
+                e.current_context(),
+                uds::Error::Adapter(
+                    elm327::Error::NotConnected | elm327::Error::Communication | elm327::Error::RequestRejected | elm327::Error::ReplayMismatch
+                )
+            ) => {
+                Err(Error::from_uds(e))
+            }
+            Err(e) => {
+                debug!("No usable answer: {:?}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Standard Mode 01 values of every PID the car supports and we know how to decode
+    pub fn get_obd2_values(&mut self) -> Result<Vec<PidValue>> {
+        self.set_address(&EcuAddress::OBD_BROADCAST)?;
+        if self.obd2_pids.is_none() {
+            let pids = mode01::supported_pids(&mut self.device).map_err(Error::from_elm327)?;
+            debug!("Supported Mode 01 PIDs: {:02X?}", pids);
+            self.obd2_pids = Some(pids);
+        }
+
+        let mut values = Vec::new();
+        for pid in self.obd2_pids.clone().unwrap_or_default() {
+            let Some(definition) = mode01::definition(pid) else {
+                continue;
+            };
+            match self.device.execute_command(mode01::PidCommand(definition)) {
+                Ok(value) => values.push(value),
+                // a supported PID can still go unanswered, e.g. speed with the car off
+                Err(e) if *e.current_context() == elm327::Error::NoData => debug!("No answer for PID {:02X}", pid),
+                Err(e) => return Err(Error::from_elm327(e)),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Passes raw CAN traffic to `on_frames` until `stop` is set, then restores the BMS setup
+    pub fn monitor_can(&mut self, settings: &MonitorSettings, stop: &AtomicBool, on_frames: impl FnMut(Vec<CanFrame>)) -> Result<()> {
+        let result = self.device
+            .monitor(settings, stop, on_frames)
+            .map_err(Error::from_elm327);
+        self.init()?;
+        result
+    }
+
+    pub fn get_cell_voltages(&mut self) -> Result<Vec<f32>> {
+        let values = self.read_signal_values(|signal| signal.group == SignalGroup::CellVoltages)?;
+        Ok(command::numbered_values(&values, command::CELL_VOLTAGE).into_iter().map(|voltage| voltage as f32).collect())
+    }
+
+    /// Reads every response the `wanted` signals are in and decodes them
+    fn read_signal_values(&mut self, wanted: impl Fn(&SignalDefinition) -> bool) -> Result<Vec<SignalValue>> {
+        let mut values = Vec::new();
+        for (ecu, request) in self.signals.requests(&wanted) {
+            let response = self.device
+                .execute_command_on(&ecu.address, self.signals.command(&ecu, &request, &wanted))
+                .map_err(Error::from_elm327)?;
+            values.extend(response);
+        }
+        Ok(values)
+    }
+
+    /// Reads one signal group. Fails with `LowVoltage` in between the slowed down reads while the
+    /// 12V battery is low.
+    pub fn read_signals(&mut self, group: SignalGroup) -> Result<Sample> {
+        let aux_battery_voltage = Self::optional(self.device.read_voltage())?;
+        if let Some(voltage) = aux_battery_voltage.filter(|voltage| self.low_voltage.should_skip(group, *voltage)) {
+            return Err(Report::new(Error::LowVoltage(voltage)));
+        }
+
+        let values = match group {
+            SignalGroup::Pack => {
+                let values = self.read_signal_values(|signal| signal.group == SignalGroup::Pack)?;
+                let battery_info = command::BatteryInfo::from_signals(values);
+                self.low_voltage.set_charging(battery_info.charging);
+                let counters = battery_info.counters;
+                if self.first_counters.is_none() {
+                    self.first_counters = counters;
+                }
+                SignalValues::Pack(PackValues {
+                    charge_level: battery_info.charge_level,
+                    available_charge_power: battery_info.available_charge_power,
+                    available_discharge_power: battery_info.available_discharge_power,
+                    charging: battery_info.charging,
+                    chademo_plugged: battery_info.chademo_plugged,
+                    j1772_plugged: battery_info.j1772_plugged,
+                    battery_current: battery_info.battery_current,
+                    battery_dc_voltage: battery_info.battery_dc_voltage,
+                    max_cell_voltage: battery_info.max_cell_voltage,
+                    max_cell_number: battery_info.max_cell_number,
+                    min_cell_voltage: battery_info.min_cell_voltage,
+                    min_cell_number: battery_info.min_cell_number,
+                    motor_speed: battery_info.motor_speed,
+                    fan_mode: battery_info.fan_mode,
+                    fan_speed: battery_info.fan_speed,
+                    isolation_resistance: battery_info.isolation_resistance,
+                    aux_battery_voltage: aux_battery_voltage.or(Some(battery_info.aux_battery_voltage)),
+                    counters,
+                    lifetime_throughput: counters.map(|counters| counters.throughput()),
+                    since_connected: counters.zip(self.first_counters).and_then(|(counters, first)| counters.since(&first)),
+                    other_signals: battery_info.other,
+                })
+            }
+            SignalGroup::CellVoltages => SignalValues::CellVoltages(CellVoltages(self.get_cell_voltages()?)),
+            // the module temperatures come with the pack values, the BMS has no block of its own for them
+            SignalGroup::Temperatures => {
+                let values = self.read_signal_values(|signal| signal.group == SignalGroup::Pack && signal.name.starts_with(command::MODULE_TEMPERATURE))?;
+                SignalValues::Temperatures(command::BatteryInfo::from_signals(values).module_temperatures)
+            }
+            SignalGroup::Health => {
+                let values = self.read_signal_values(|signal| signal.group == SignalGroup::Health)?;
+                SignalValues::Health(command::BatteryHealth::from_signals(values))
+            }
+        };
+        self.low_voltage.record_read(group);
+        Ok(Sample {
+            time: SampleTime(std::time::SystemTime::now()),
+            values,
         })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::capture::NoCapture;
+    use profile::SoulEv;
+    use simulator::{PackState, Simulator};
+
+    fn pack() -> PackState {
+        let mut pack = PackState {
+            state_of_charge: 63.5,
+            battery_current: -41.7,
+            module_temperatures: [18, 19, 20, 21, 22, 23, -4],
+            inlet_temperature: 17,
+            heater_temperatures: [12, 13],
+            charging: true,
+            chademo_plugged: true,
+            j1772_plugged: false,
+            available_charge_power: 48.25,
+            available_discharge_power: 97.5,
+            fan_status: 2,
+            fan_feedback: 25,
+            aux_battery_voltage: 13.8,
+            operating_time: 7_654_321,
+            motor_rpm: -850,
+            isolation_resistance: 1234,
+            max_deterioration: 14.2,
+            max_deterioration_cell: 61,
+            min_deterioration: 6.3,
+            min_deterioration_cell: 7,
+            state_of_health: 85.9,
+            ..PackState::default()
+        };
+        for (cell, voltage) in pack.cell_voltages.iter_mut().enumerate() {
+            *voltage = 3.80 + (cell % 10) as f64 * 0.02;
+        }
+        pack.cell_voltages[40] = 4.10;
+        pack.cell_voltages[71] = 3.62;
+        pack
+    }
+
+    fn kia(pack: PackState) -> Kia {
+        let device = Elm327::new(Box::new(Simulator::new(pack)), Box::new(NoCapture)).unwrap();
+        let signals = SignalSet::load(&SoulEv, None).unwrap();
+        let mut kia = Kia::new(device, Box::new(SoulEv), signals);
+        kia.init().unwrap();
+        kia
+    }
+
+    fn assert_close(actual: f64, expected: f64, precision: f64) {
+        assert!((actual - expected).abs() <= precision / 2.0, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn pack_values() {
+        let pack = pack();
+        let mut kia = kia(pack.clone());
+
+        let SignalValues::Pack(values) = kia.read_signals(SignalGroup::Pack).unwrap().values else {
+            panic!("not the pack values");
+        };
+        assert_close(values.charge_level, pack.state_of_charge, 0.5);
+        assert_close(values.available_charge_power, pack.available_charge_power, 0.01);
+        assert_close(values.available_discharge_power, pack.available_discharge_power, 0.01);
+        assert_eq!(values.charging, pack.charging);
+        assert_eq!(values.chademo_plugged, pack.chademo_plugged);
+        assert_eq!(values.j1772_plugged, pack.j1772_plugged);
+        assert_close(values.battery_current, pack.battery_current, 0.1);
+        assert_close(values.battery_dc_voltage, pack.battery_dc_voltage(), 0.1);
+        assert_close(values.max_cell_voltage, 4.10, 0.02);
+        assert_eq!(values.max_cell_number, 41);
+        assert_close(values.min_cell_voltage, 3.62, 0.02);
+        assert_eq!(values.min_cell_number, 72);
+        assert_eq!(values.motor_speed, pack.motor_rpm);
+        assert_eq!(values.fan_mode, pack.fan_status);
+        assert_eq!(values.fan_speed, pack.fan_feedback);
+        assert_eq!(values.isolation_resistance, Some(pack.isolation_resistance));
+        assert_close(values.aux_battery_voltage.unwrap(), pack.aux_battery_voltage, 0.1);
+
+        let counters = values.counters.unwrap();
+        assert_close(counters.charge_current, pack.cumulative_charge_current, 0.1);
+        assert_close(counters.discharge_current, pack.cumulative_discharge_current, 0.1);
+        assert_close(counters.energy_charged, pack.cumulative_energy_charged, 0.1);
+        assert_close(counters.energy_discharged, pack.cumulative_energy_discharged, 0.1);
+        assert_eq!(counters.operating_time, pack.operating_time);
+        assert!(values.other_signals.is_empty());
+
+        let SignalValues::Temperatures(temperatures) = kia.read_signals(SignalGroup::Temperatures).unwrap().values else {
+            panic!("not the temperatures");
+        };
+        assert_eq!(temperatures, pack.module_temperatures.to_vec());
+    }
+
+    #[test]
+    fn cell_voltages() {
+        let pack = pack();
+        let mut kia = kia(pack.clone());
+
+        let SignalValues::CellVoltages(CellVoltages(voltages)) = kia.read_signals(SignalGroup::CellVoltages).unwrap().values else {
+            panic!("not the cell voltages");
+        };
+        assert_eq!(voltages.len(), pack.cell_voltages.len());
+        for (actual, expected) in voltages.iter().zip(pack.cell_voltages) {
+            assert_close(*actual as f64, expected, 0.02);
+        }
+    }
+
+    #[test]
+    fn battery_health() {
+        let pack = pack();
+        let mut kia = kia(pack.clone());
+
+        let SignalValues::Health(health) = kia.read_signals(SignalGroup::Health).unwrap().values else {
+            panic!("not the battery health");
+        };
+        assert_close(health.state_of_health, pack.state_of_health, 0.1);
+        assert_close(health.max_deterioration, pack.max_deterioration, 0.1);
+        assert_eq!(health.max_deterioration_cell, pack.max_deterioration_cell);
+        assert_close(health.min_deterioration, pack.min_deterioration, 0.1);
+        assert_eq!(health.min_deterioration_cell, pack.min_deterioration_cell);
+        assert_eq!(health.inlet_temperature, Some(pack.inlet_temperature));
+        assert_eq!(health.heater_temperatures, Some(pack.heater_temperatures));
+        assert!(health.other_signals.is_empty(), "{:?}", health.other_signals);
+    }
+}
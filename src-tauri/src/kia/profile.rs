@@ -0,0 +1,319 @@
+use serde::Serialize;
+use super::{DiagnosticEcu, BMS, VMCU};
+use super::command::{CELL_VOLTAGE, MODULE_TEMPERATURE};
+use super::signals::SignalSet;
+
+// What sets the Hyundai/Kia EVs apart when reading their battery. The first generation BMSs
+// (Soul EV, Ioniq) answer KWP `21 0X` requests, the 64 kWh ones UDS `22 01 0X` with one more
+// header byte. The field order is the same in both, only the number of module temperatures
+// moves the fields behind them. The 64 kWh BMSs have 2 more cells, their voltages come with the
+// state of health. Every value read from the BMS is described in the files in `signals/`.
+//
+// The Soul EV offsets follow SoulEVSpy's BatteryManagementSystemParser. The other profiles' are
+// derived from them by the rule above: the Ioniq's 5 temperatures move everything behind them
+// 2 bytes forward, on the 64 kWh BMSs the header byte moves it 1 byte back again. None of them
+// has been checked against a capture of that car yet, so they are marked experimental until
+// then. An override file (see `SignalSet::load`) corrects them without a new build.
+
+pub trait VehicleProfile: Send {
+    /// Passed to `connect` and written to captures
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    /// Sent after the adapter setup. `01 00` has to be answered, that's how we know the car is on.
+    fn init_commands(&self) -> &'static [&'static str];
+    /// Sent to the BMS once it is addressed
+    fn bms_init_commands(&self) -> &'static [&'static str];
+    fn bms(&self) -> DiagnosticEcu {
+        BMS
+    }
+    /// ECUs with their own fault memory
+    fn diagnostic_ecus(&self) -> &'static [DiagnosticEcu] {
+        &[BMS, VMCU]
+    }
+    /// Built-in signal definitions, see `signals::SignalSet`
+    fn signals(&self) -> &'static str;
+    /// The offsets haven't been checked against a capture of the car
+    fn experimental(&self) -> bool {
+        false
+    }
+}
+
+/// What the frontend gets to know about a profile
+#[derive(Serialize, Debug, Clone)]
+pub struct ProfileInfo {
+    id: &'static str,
+    name: &'static str,
+    cell_count: usize,
+    module_count: usize,
+    experimental: bool,
+}
+
+impl ProfileInfo {
+    /// The cells and modules are counted in `signals`, an override file may have more or less
+    pub fn new(profile: &dyn VehicleProfile, signals: &SignalSet) -> Self {
+        Self {
+            id: profile.id(),
+            name: profile.name(),
+            cell_count: signals.count(CELL_VOLTAGE),
+            module_count: signals.count(MODULE_TEMPERATURE),
+            experimental: profile.experimental(),
+        }
+    }
+}
+
+/// ISO 15765-4 CAN with 11-bit IDs at 500 kbaud, then the OBD-II requests the VMCU answers
+const CAN_INIT: [&str; 5] = ["AT SPA6", "01 00", "01 20", "09 00", "01 01"];
+
+/// Kia Soul EV 27/30 kWh, 2014-2019
+pub struct SoulEv;
+
+impl VehicleProfile for SoulEv {
+    fn id(&self) -> &'static str {
+        "kia_soul_ev"
+    }
+    fn name(&self) -> &'static str {
+        "Kia Soul EV 2014-2019"
+    }
+    fn init_commands(&self) -> &'static [&'static str] {
+        &CAN_INIT
+    }
+    fn bms_init_commands(&self) -> &'static [&'static str] {
+        &["21 00"]
+    }
+    fn signals(&self) -> &'static str {
+        include_str!("../../signals/kia_soul_ev.json")
+    }
+}
+
+/// Hyundai Ioniq Electric 28 kWh, 2016-2019
+pub struct IoniqElectric;
+
+impl VehicleProfile for IoniqElectric {
+    fn id(&self) -> &'static str {
+        "hyundai_ioniq_electric_28"
+    }
+    fn name(&self) -> &'static str {
+        "Hyundai Ioniq Electric 28 kWh"
+    }
+    fn init_commands(&self) -> &'static [&'static str] {
+        &CAN_INIT
+    }
+    fn bms_init_commands(&self) -> &'static [&'static str] {
+        &["21 00"]
+    }
+    fn signals(&self) -> &'static str {
+        include_str!("../../signals/hyundai_ioniq_electric_28.json")
+    }
+    fn experimental(&self) -> bool {
+        true
+    }
+}
+
+/// The 64 kWh BMSs share one layout, the same block is read on all of them
+const UDS_SIGNALS: &str = include_str!("../../signals/bms_64kwh.json");
+
+/// Kia Soul EV 64 kWh, 2019 on
+pub struct SoulEv64;
+
+impl VehicleProfile for SoulEv64 {
+    fn id(&self) -> &'static str {
+        "kia_soul_ev_64"
+    }
+    fn name(&self) -> &'static str {
+        "Kia Soul EV 64 kWh"
+    }
+    fn init_commands(&self) -> &'static [&'static str] {
+        &CAN_INIT
+    }
+    fn bms_init_commands(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn signals(&self) -> &'static str {
+        UDS_SIGNALS
+    }
+    fn experimental(&self) -> bool {
+        true
+    }
+}
+
+/// Hyundai Kona Electric and Kia e-Niro 64 kWh
+pub struct Kona64;
+
+impl VehicleProfile for Kona64 {
+    fn id(&self) -> &'static str {
+        "hyundai_kona_64"
+    }
+    fn name(&self) -> &'static str {
+        "Hyundai Kona Electric / Kia e-Niro 64 kWh"
+    }
+    fn init_commands(&self) -> &'static [&'static str] {
+        &CAN_INIT
+    }
+    fn bms_init_commands(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn signals(&self) -> &'static str {
+        UDS_SIGNALS
+    }
+    fn experimental(&self) -> bool {
+        true
+    }
+}
+
+pub fn profiles() -> Vec<Box<dyn VehicleProfile>> {
+    vec![Box::new(SoulEv), Box::new(SoulEv64), Box::new(IoniqElectric), Box::new(Kona64)]
+}
+
+pub fn profile(id: &str) -> Option<Box<dyn VehicleProfile>> {
+    profiles().into_iter().find(|profile| profile.id() == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elm327::Command;
+    use crate::kia::SignalGroup;
+    use crate::kia::command::{numbered_values, BatteryHealth, BatteryInfo};
+    use crate::kia::signals::SignalDefinition;
+
+    /// Main BMS block laid out by the rule in the header comment, `header` comes before the
+    /// charge level. For the experimental profiles this only shows that their definitions
+    /// follow the rule, whether the rule holds takes a capture of the car.
+    fn main_block(header: &[u8], modules: usize) -> Vec<u8> {
+        let mut payload = header.to_vec();
+        payload.extend([0xBC, 0x26, 0x48, 0x27, 0x10, 0xA0, 0xFF, 0x85, 0x0E, 0x69]);
+        // max and min temperature, one per module, inlet
+        payload.extend([0x10, 0xFE]);
+        payload.extend((0..modules).map(|module| 10 + module as u8));
+        payload.push(0x11);
+        payload.extend([0xCA, 0x2A, 0xC8, 0x55, 0x01, 0x1E, 0x8F]);
+        for counter in [27556u32, 28146, 9874, 10154, 5354723] {
+            payload.extend(counter.to_be_bytes());
+        }
+        // operating state and DC voltage, then the motor speed twice
+        payload.extend([0x0D, 0x01, 0x71, 0xFB, 0x2E, 0xFB, 0x2E, 0x03, 0xE8]);
+        payload
+    }
+
+    /// ISO-TP frames of `payload` as the adapter prints them with headers on
+    fn frames(payload: &[u8]) -> String {
+        let mut frames = vec![[&[0x10 | (payload.len() >> 8) as u8, payload.len() as u8], &payload[..6]].concat()];
+        for (sequence, chunk) in payload[6..].chunks(7).enumerate() {
+            frames.push([&[0x20 | ((sequence + 1) % 16) as u8], chunk].concat());
+        }
+        frames
+            .into_iter()
+            .map(|mut frame| {
+                frame.resize(8, 0x00);
+                let bytes: Vec<String> = frame.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("7EC {}\n", bytes.join(" "))
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    fn check_main_block(profile: &dyn VehicleProfile, header: &[u8], modules: usize, cells: usize) {
+        let signals = SignalSet::parse(profile.id(), profile.signals()).unwrap();
+        let info = ProfileInfo::new(profile, &signals);
+        assert_eq!((info.module_count, info.cell_count), (modules, cells), "{}", profile.id());
+        let requests = signals.requests(|signal| signal.group == SignalGroup::Pack);
+        assert_eq!(requests.len(), 1, "{}", profile.id());
+        let (ecu, request) = &requests[0];
+        let response = frames(&main_block(header, modules));
+        let info = BatteryInfo::from_signals(signals.command(ecu, request, |_| true).parse_result(response).unwrap());
+
+        assert_close(info.charge_level, 94.0);
+        assert_close(info.available_charge_power, 98.0);
+        assert_close(info.available_discharge_power, 100.0);
+        assert!(info.charging && !info.chademo_plugged && info.j1772_plugged);
+        assert_close(info.battery_current, -12.3);
+        assert_close(info.battery_dc_voltage, 368.9);
+        let temperatures: Vec<i32> = (0..modules as i32).map(|module| 10 + module).collect();
+        assert_eq!(info.module_temperatures, temperatures);
+        assert_close(info.max_cell_voltage, 4.04);
+        assert_eq!(info.max_cell_number, 42);
+        assert_close(info.min_cell_voltage, 4.0);
+        assert_eq!(info.min_cell_number, 85);
+        assert_eq!((info.fan_mode, info.fan_speed), (1, 30));
+        assert_close(info.aux_battery_voltage, 14.3);
+        let counters = info.counters.unwrap();
+        assert_close(counters.charge_current, 2755.6);
+        assert_close(counters.discharge_current, 2814.6);
+        assert_close(counters.energy_charged, 987.4);
+        assert_close(counters.energy_discharged, 1015.4);
+        assert_eq!(counters.operating_time, 5354723);
+        assert_eq!(info.motor_speed, -1234);
+        assert_eq!(info.isolation_resistance, Some(1000));
+    }
+
+    const KWP_HEADER: [u8; 6] = [0x61, 0x01, 0xFF, 0xFF, 0xFF, 0xFF];
+    const UDS_HEADER: [u8; 7] = [0x62, 0x01, 0x01, 0xFF, 0xFF, 0xFF, 0xFF];
+
+    #[test]
+    fn soul_ev() {
+        check_main_block(&SoulEv, &KWP_HEADER, 7, 96);
+    }
+
+    #[test]
+    fn ioniq_electric() {
+        check_main_block(&IoniqElectric, &KWP_HEADER, 5, 96);
+    }
+
+    #[test]
+    fn soul_ev_64() {
+        check_main_block(&SoulEv64, &UDS_HEADER, 5, 98);
+    }
+
+    #[test]
+    fn kona_64() {
+        check_main_block(&Kona64, &UDS_HEADER, 5, 98);
+    }
+
+    #[test]
+    fn uds_health_block() {
+        // the state of health block of the 64 kWh BMSs ends with the voltages of the last 2 cells
+        let mut payload = vec![0xFF; 34];
+        payload[..3].copy_from_slice(&[0x62, 0x01, 0x05]);
+        payload[23..29].copy_from_slice(&[0x00, 0x8E, 0x3D, 0x00, 0x3F, 0x07]);
+        payload[30..34].copy_from_slice(&[0x03, 0x5B, 0xC8, 0xC9]);
+        let signals = SignalSet::parse(Kona64.id(), Kona64.signals()).unwrap();
+
+        let health = |signal: &SignalDefinition| signal.group == SignalGroup::Health;
+        let requests = signals.requests(health);
+        assert_eq!(requests.len(), 1);
+        let (ecu, request) = &requests[0];
+        assert_eq!(request, "22 01 05");
+        let values = signals.command(ecu, request, health).parse_result(frames(&payload)).unwrap();
+        let health = BatteryHealth::from_signals(values);
+        assert_close(health.state_of_health, 85.9);
+        assert_close(health.max_deterioration, 14.2);
+        assert_eq!(health.max_deterioration_cell, 61);
+        assert_close(health.min_deterioration, 6.3);
+        assert_eq!(health.min_deterioration_cell, 7);
+        assert_eq!((health.inlet_temperature, health.heater_temperatures), (None, None));
+        assert!(health.other_signals.is_empty(), "{:?}", health.other_signals);
+
+        let cells = signals.command(ecu, request, |signal| signal.group == SignalGroup::CellVoltages)
+            .parse_result(frames(&payload))
+            .unwrap();
+        assert_eq!(numbered_values(&cells, CELL_VOLTAGE), vec![0xC8 as f64 * 0.02, 0xC9 as f64 * 0.02]);
+    }
+
+    #[test]
+    fn experimental_profiles() {
+        // only the Soul EV layout is known from real responses
+        for profile in profiles() {
+            let signals = SignalSet::load(profile.as_ref(), None).unwrap();
+            let info = ProfileInfo::new(profile.as_ref(), &signals);
+            assert_eq!(info.experimental, profile.id() != SoulEv.id(), "{}", profile.id());
+        }
+    }
+
+    #[test]
+    fn demo_profile() {
+        assert!(profile(crate::kia::simulator::Simulator::PROFILE).is_some());
+    }
+}
@@ -0,0 +1,318 @@
+use std::fs;
+use std::path::Path;
+use error_stack::{Report, ResultExt};
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::elm327;
+use crate::elm327::{isotp, Command};
+use super::{DiagnosticEcu, SignalGroup, BMS, VMCU};
+use super::profile::VehicleProfile;
+
+type Result<T> = error_stack::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Can't read {0}")]
+    Read(String),
+    #[error("Bad signal definitions in {0}: {1}")]
+    Invalid(String, String),
+}
+
+/// ECUs a definition can name
+const ECUS: [DiagnosticEcu; 2] = [BMS, VMCU];
+
+fn default_ecu() -> String {
+    BMS.name.to_string()
+}
+
+fn default_group() -> SignalGroup {
+    SignalGroup::Pack
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Number of a signal named `prefix` and a number, e.g. 3 of `module_temperature_3`
+pub fn signal_number(name: &str, prefix: &str) -> Option<usize> {
+    name.strip_prefix(prefix).and_then(|number| number.parse::<usize>().ok())
+}
+
+/// Where a value sits in an ECU response and how it is scaled, value = raw * scale + offset
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SignalDefinition {
+    pub name: String,
+    /// Read along with the other signals of the group, module temperatures are read with the
+    /// pack values
+    #[serde(default = "default_group")]
+    pub group: SignalGroup,
+    /// Request the ECU answers with the value, e.g. `21 01`
+    pub request: String,
+    #[serde(default = "default_ecu")]
+    pub ecu: String,
+    /// Counted from the response SID
+    pub byte_offset: usize,
+    /// Counted from the most significant bit of the first byte, the value is big endian
+    #[serde(default)]
+    pub bit_offset: u8,
+    pub bit_length: u8,
+    /// Two's complement
+    #[serde(default)]
+    pub signed: bool,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    #[serde(default)]
+    pub unit: String,
+    /// Left out when the response ends before it instead of failing the read
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl SignalDefinition {
+    /// `None` when the payload ends before the value
+    pub fn decode(&self, payload: &[u8]) -> Option<f64> {
+        let end_bit = self.bit_offset as usize + self.bit_length as usize;
+        let bytes = payload.get(self.byte_offset..self.byte_offset + end_bit.div_ceil(8))?;
+        let raw = bytes.iter().fold(0u64, |raw, byte| raw << 8 | *byte as u64);
+        let raw = (raw >> (bytes.len() * 8 - end_bit)) & ((1u64 << self.bit_length) - 1);
+        let raw = if self.signed && raw >> (self.bit_length - 1) != 0 {
+            raw as i64 - (1i64 << self.bit_length)
+        } else {
+            raw as i64
+        };
+        Some(raw as f64 * self.scale + self.offset)
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.bit_offset > 7 {
+            return Err(format!("{}: bit_offset has to be 0 to 7", self.name));
+        }
+        if self.bit_length == 0 || self.bit_length > 32 {
+            return Err(format!("{}: bit_length has to be 1 to 32", self.name));
+        }
+        if self.group == SignalGroup::Temperatures {
+            return Err(format!("{}: temperatures are read with the pack values, use group pack", self.name));
+        }
+        if !ECUS.iter().any(|ecu| ecu.name == self.ecu) {
+            return Err(format!("{}: unknown ECU {}", self.name, self.ecu));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SignalValue {
+    pub name: String,
+    pub unit: String,
+    pub value: f64,
+}
+
+/// The signals read from a car, in the order of their definition file
+pub struct SignalSet {
+    definitions: Vec<SignalDefinition>,
+}
+
+impl SignalSet {
+    /// `source` names the definitions in errors
+    pub fn parse(source: &str, json: &str) -> Result<Self> {
+        let definitions: Vec<SignalDefinition> = serde_json::from_str(json)
+            .map_err(|e| Report::new(Error::Invalid(source.to_string(), e.to_string())))?;
+        for definition in &definitions {
+            definition.validate().map_err(|message| Report::new(Error::Invalid(source.to_string(), message)))?;
+        }
+        Ok(Self { definitions })
+    }
+
+    /// `<profile id>.json` in `directory` when there is one, the definitions built into the
+    /// profile otherwise
+    pub fn load(profile: &dyn VehicleProfile, directory: Option<&Path>) -> Result<Self> {
+        let path = directory.map(|directory| directory.join(format!("{}.json", profile.id())));
+        match path {
+            Some(path) if path.exists() => {
+                info!("Reading signal definitions from {}", path.display());
+                let source = path.display().to_string();
+                let json = fs::read_to_string(&path).change_context(Error::Read(source.clone()))?;
+                Self::parse(&source, &json)
+            }
+            _ => Self::parse(profile.id(), profile.signals()),
+        }
+    }
+
+    /// Number of signals named `prefix` and a number, e.g. the module temperatures
+    pub fn count(&self, prefix: &str) -> usize {
+        self.definitions
+            .iter()
+            .filter(|definition| signal_number(&definition.name, prefix).is_some())
+            .count()
+    }
+
+    /// ECU and request of every response the `wanted` signals are in, each of them once
+    pub fn requests(&self, wanted: impl Fn(&SignalDefinition) -> bool) -> Vec<(DiagnosticEcu, String)> {
+        let mut requests: Vec<(DiagnosticEcu, String)> = Vec::new();
+        for definition in self.definitions.iter().filter(|definition| wanted(definition)) {
+            let ecu = ECUS.into_iter().find(|ecu| ecu.name == definition.ecu).unwrap_or(BMS);
+            if !requests.iter().any(|(known, request)| *known == ecu && *request == definition.request) {
+                requests.push((ecu, definition.request.clone()));
+            }
+        }
+        requests
+    }
+
+    /// Decodes the `wanted` signals `request` to `ecu` answers with
+    pub fn command<'a>(&'a self, ecu: &DiagnosticEcu, request: &'a str, wanted: impl Fn(&SignalDefinition) -> bool) -> SignalsCommand<'a> {
+        SignalsCommand {
+            request,
+            signals: self.definitions
+                .iter()
+                .filter(|definition| definition.ecu == ecu.name && definition.request == request && wanted(definition))
+                .collect(),
+        }
+    }
+}
+
+pub struct SignalsCommand<'a> {
+    request: &'a str,
+    signals: Vec<&'a SignalDefinition>,
+}
+
+impl Command for SignalsCommand<'_> {
+    type Response = Vec<SignalValue>;
+
+    fn serial_command(&self) -> String {
+        self.request.to_string()
+    }
+
+    fn parse_result(&self, response: String) -> elm327::error::Result<Self::Response> {
+        let payload = isotp::single_payload(&response).change_context(elm327::Error::Other)?;
+        let mut values = Vec::with_capacity(self.signals.len());
+        for signal in &self.signals {
+            match signal.decode(&payload) {
+                Some(value) => values.push(SignalValue {
+                    name: signal.name.clone(),
+                    unit: signal.unit.clone(),
+                    value,
+                }),
+                None if signal.optional => {}
+                None => {
+                    return Err(Report::new(elm327::Error::Other)
+                        .attach_printable(format!("{} is {} bytes long, {} doesn't fit", self.request, payload.len(), signal.name)));
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(byte_offset: usize, bit_offset: u8, bit_length: u8, signed: bool) -> SignalDefinition {
+        SignalDefinition {
+            name: "value".to_string(),
+            group: SignalGroup::Pack,
+            request: "21 01".to_string(),
+            ecu: default_ecu(),
+            byte_offset,
+            bit_offset,
+            bit_length,
+            signed,
+            scale: 1.0,
+            offset: 0.0,
+            unit: String::new(),
+            optional: false,
+        }
+    }
+
+    #[test]
+    fn bits_inside_a_byte() {
+        let payload = [0x61, 0b1010_0110];
+        assert_eq!(definition(1, 0, 1, false).decode(&payload), Some(1.0));
+        assert_eq!(definition(1, 1, 1, false).decode(&payload), Some(0.0));
+        assert_eq!(definition(1, 2, 3, false).decode(&payload), Some(0b100 as f64));
+        assert_eq!(definition(1, 5, 3, false).decode(&payload), Some(0b110 as f64));
+        // the sign bit is the first of the field, not of the byte
+        assert_eq!(definition(1, 5, 3, true).decode(&payload), Some(-2.0));
+    }
+
+    #[test]
+    fn bits_across_bytes() {
+        assert_eq!(definition(0, 4, 8, false).decode(&[0xAB, 0xCD]), Some(0xBC as f64));
+        assert_eq!(definition(0, 6, 4, false).decode(&[0x03, 0xC0]), Some(0xF as f64));
+    }
+
+    #[test]
+    fn signed_12_bit() {
+        assert_eq!(definition(0, 0, 12, true).decode(&[0xF8, 0x30]), Some(-125.0));
+        assert_eq!(definition(0, 0, 12, true).decode(&[0x7F, 0xF0]), Some(2047.0));
+        assert_eq!(definition(0, 0, 12, true).decode(&[0x80, 0x00]), Some(-2048.0));
+        assert_eq!(definition(0, 4, 12, true).decode(&[0xF0, 0x01]), Some(1.0));
+        assert_eq!(definition(0, 4, 12, true).decode(&[0x0F, 0xFF]), Some(-1.0));
+        assert_eq!(definition(0, 4, 12, false).decode(&[0x0F, 0xFF]), Some(4095.0));
+    }
+
+    #[test]
+    fn signed_16_bit() {
+        assert_eq!(definition(0, 0, 16, true).decode(&[0xFB, 0x2E]), Some(-1234.0));
+        assert_eq!(definition(0, 0, 16, true).decode(&[0x80, 0x00]), Some(-32768.0));
+        assert_eq!(definition(0, 0, 16, true).decode(&[0x7F, 0xFF]), Some(32767.0));
+        assert_eq!(definition(0, 0, 16, false).decode(&[0xFB, 0x2E]), Some(64302.0));
+
+        let mut current = definition(0, 0, 16, true);
+        current.scale = 0.1;
+        let value = current.decode(&[0xFF, 0x85]).unwrap();
+        assert!((value - -12.3).abs() < 1e-9, "{}", value);
+    }
+
+    #[test]
+    fn counters_32_bit() {
+        assert_eq!(definition(1, 0, 32, false).decode(&[0x61, 0x00, 0x51, 0xB4, 0xE3]), Some(5354723.0));
+        assert_eq!(definition(0, 0, 32, false).decode(&[0xFF, 0xFF, 0xFF, 0xFF]), Some(4294967295.0));
+        assert_eq!(definition(0, 0, 32, true).decode(&[0xFF, 0xFF, 0xFF, 0xFF]), Some(-1.0));
+
+        let mut energy = definition(0, 0, 32, false);
+        energy.scale = 0.1;
+        let value = energy.decode(&[0x00, 0x00, 0x26, 0x92]).unwrap();
+        assert!((value - 987.4).abs() < 1e-9, "{}", value);
+    }
+
+    #[test]
+    fn scale_and_offset() {
+        let mut temperature = definition(0, 0, 8, false);
+        temperature.offset = -40.0;
+        assert_eq!(temperature.decode(&[0x3C]), Some(20.0));
+    }
+
+    #[test]
+    fn payload_too_short() {
+        let payload = [0x61, 0x01, 0xFF, 0x85];
+        assert_eq!(definition(3, 0, 16, false).decode(&payload), None);
+        assert_eq!(definition(3, 4, 8, false).decode(&payload), None);
+        assert_eq!(definition(4, 0, 1, false).decode(&payload), None);
+        assert_eq!(definition(1, 0, 32, false).decode(&payload), None);
+        assert_eq!(definition(3, 0, 8, false).decode(&payload), Some(0x85 as f64));
+    }
+
+    #[test]
+    fn counts_numbered_signals() {
+        let signals = SignalSet::parse("test", r#"[
+            {"name": "module_temperature_1", "request": "21 01", "byte_offset": 6, "bit_length": 8},
+            {"name": "module_temperature_2", "request": "21 01", "byte_offset": 7, "bit_length": 8},
+            {"name": "module_temperature_max", "request": "21 01", "byte_offset": 8, "bit_length": 8},
+            {"name": "cell_voltage_1", "group": "cell_voltages", "request": "21 02", "byte_offset": 6, "bit_length": 8}
+        ]"#).unwrap();
+        assert_eq!(signals.count("module_temperature_"), 2);
+        assert_eq!(signals.count("cell_voltage_"), 1);
+        let requests: Vec<String> = signals.requests(|signal| signal.group == SignalGroup::Pack).into_iter().map(|(_, request)| request).collect();
+        assert_eq!(requests, vec!["21 01"]);
+    }
+
+    #[test]
+    fn temperatures_group_is_refused() {
+        let json = r#"[{"name": "module_temperature_1", "group": "temperatures", "request": "21 01", "byte_offset": 6, "bit_length": 8}]"#;
+        assert!(SignalSet::parse("test", json).is_err());
+    }
+}
@@ -0,0 +1,677 @@
+// Emulation of an ELM327 attached to the Soul EV CAN bus. Only depends on std and rand so the
+// elm327-emu binary can share it with the in-process simulator transport.
+//
+// BMS block layouts follow https://github.com/langemand/SoulEVSpy/blob/master/app/src/main/java/com/evranger/soulevspy/util/BatteryManagementSystemParser.java
+
+use std::sync::{Arc, Mutex};
+use rand::Rng;
+
+pub const CELL_COUNT: usize = 96;
+pub const DEVICE_ID: &str = "ELM327 v1.5";
+const DEVICE_DESCRIPTION: &str = "OBDII to RS232 Interpreter";
+const FUNCTIONAL_HEADER: u32 = 0x7DF;
+/// ISO 15765-4 functional requests with 29-bit IDs
+const FUNCTIONAL_EXTENDED_HEADER: u32 = 0x18DB33F1;
+/// Priority bits of 29-bit headers until `AT CP` changes them
+const DEFAULT_PRIORITY: u32 = 0x18;
+/// Mode 01 PIDs the VMCU answers, besides the bitmaps
+const VMCU_PIDS: [u8; 5] = [0x01, 0x0D, 0x42, 0x46, 0xA6];
+const VIN: &str = "KNAJX81EFF7012345";
+const VMCU_CALIBRATION_ID: &str = "E4VMCU1.02";
+const VMCU_NAME: &str = "VMCU-VehicleMotorCtl";
+const BMS_PART_NUMBER: &str = "37501-E4000";
+const BMS_SOFTWARE_VERSION: &str = "E4BMS 1.06";
+
+#[derive(Debug, Clone)]
+pub struct PackState {
+    /// State of charge as reported by the BMS, %
+    pub state_of_charge: f64,
+    /// Positive when discharging, A
+    pub battery_current: f64,
+    pub cell_voltages: [f64; CELL_COUNT],
+    pub module_temperatures: [i32; 7],
+    pub inlet_temperature: i32,
+    pub heater_temperatures: [i32; 2],
+    pub charging: bool,
+    pub chademo_plugged: bool,
+    pub j1772_plugged: bool,
+    pub available_charge_power: f64,
+    pub available_discharge_power: f64,
+    pub fan_status: u8,
+    pub fan_feedback: u8,
+    pub aux_battery_voltage: f64,
+    pub cumulative_charge_current: f64,
+    pub cumulative_discharge_current: f64,
+    pub cumulative_energy_charged: f64,
+    pub cumulative_energy_discharged: f64,
+    pub operating_time: u32,
+    pub motor_rpm: i32,
+    pub isolation_resistance: u16,
+    pub max_deterioration: f64,
+    pub max_deterioration_cell: u8,
+    pub min_deterioration: f64,
+    pub min_deterioration_cell: u8,
+    pub state_of_health: f64,
+    /// km/h
+    pub vehicle_speed: f64,
+    pub ambient_temperature: i32,
+    /// km
+    pub odometer: f64,
+    /// Stored OBD codes of the VMCU, two byte form
+    pub stored_dtcs: Vec<u16>,
+    /// BMS fault memory, three byte DTC and status
+    pub bms_dtcs: Vec<(u32, u8)>,
+}
+
+impl Default for PackState {
+    fn default() -> Self {
+        Self {
+            state_of_charge: 72.5,
+            battery_current: 3.2,
+            cell_voltages: [3.94; CELL_COUNT],
+            module_temperatures: [21, 21, 22, 22, 21, 20, 21],
+            inlet_temperature: 20,
+            heater_temperatures: [19, 19],
+            charging: false,
+            chademo_plugged: false,
+            j1772_plugged: false,
+            available_charge_power: 60.0,
+            available_discharge_power: 90.0,
+            fan_status: 0,
+            fan_feedback: 0,
+            aux_battery_voltage: 14.2,
+            cumulative_charge_current: 41_250.3,
+            cumulative_discharge_current: 40_120.8,
+            cumulative_energy_charged: 14_820.4,
+            cumulative_energy_discharged: 14_205.9,
+            operating_time: 9_845_120,
+            motor_rpm: 0,
+            isolation_resistance: 1000,
+            max_deterioration: 12.4,
+            max_deterioration_cell: 37,
+            min_deterioration: 8.1,
+            min_deterioration_cell: 80,
+            state_of_health: 87.6,
+            vehicle_speed: 0.0,
+            ambient_temperature: 18,
+            odometer: 48_213.7,
+            stored_dtcs: Vec::new(),
+            // P0A7F-00 hybrid battery pack deterioration, confirmed in an earlier cycle
+            bms_dtcs: vec![(0x0A7F00, 0x28)],
+        }
+    }
+}
+
+impl PackState {
+    pub fn battery_dc_voltage(&self) -> f64 {
+        self.cell_voltages.iter().sum()
+    }
+
+    fn max_cell(&self) -> (usize, f64) {
+        self.cell_voltages
+            .iter()
+            .enumerate()
+            .fold((0, f64::MIN), |acc, (i, v)| if *v > acc.1 { (i, *v) } else { acc })
+    }
+
+    fn min_cell(&self) -> (usize, f64) {
+        self.cell_voltages
+            .iter()
+            .enumerate()
+            .fold((0, f64::MAX), |acc, (i, v)| if *v < acc.1 { (i, *v) } else { acc })
+    }
+
+    /// Small random changes so the demo charts look alive
+    pub fn jitter(&mut self) {
+        let mut rng = rand::thread_rng();
+        self.battery_current = (self.battery_current + rng.gen_range(-2.0..2.0)).clamp(-50.0, 150.0);
+        for v in self.cell_voltages.iter_mut() {
+            *v = (*v + rng.gen_range(-0.02..0.02)).clamp(3.5, 4.2);
+        }
+
+        // about a second passes between reads
+        let ampere_hours = self.battery_current.abs() / 3600.0;
+        let kilowatt_hours = ampere_hours * self.battery_dc_voltage() / 1000.0;
+        if self.battery_current < 0.0 {
+            self.cumulative_charge_current += ampere_hours;
+            self.cumulative_energy_charged += kilowatt_hours;
+        } else {
+            self.cumulative_discharge_current += ampere_hours;
+            self.cumulative_energy_discharged += kilowatt_hours;
+        }
+        self.operating_time += 1;
+    }
+
+    /// `21 01` payload
+    pub fn battery_info_block(&self) -> Vec<u8> {
+        let mut flags = 0u8;
+        if self.charging {
+            flags |= 1 << 7;
+        }
+        if self.chademo_plugged {
+            flags |= 1 << 6;
+        }
+        if self.j1772_plugged {
+            flags |= 1 << 5;
+        }
+        let (max_cell_no, max_cell_voltage) = self.max_cell();
+        let (min_cell_no, min_cell_voltage) = self.min_cell();
+
+        let mut payload = vec![0x61, 0x01, 0xFF, 0xFF, 0xFF, 0xFF];
+        payload.push((self.state_of_charge * 2.0).round() as u8);
+        payload.extend(u16_bytes(self.available_charge_power * 100.0));
+        payload.extend(u16_bytes(self.available_discharge_power * 100.0));
+        payload.push(flags);
+        payload.extend(((self.battery_current * 10.0).round() as i16).to_be_bytes());
+        payload.extend(u16_bytes(self.battery_dc_voltage() * 10.0));
+        payload.push(*self.module_temperatures.iter().max().unwrap() as i8 as u8);
+        payload.push(*self.module_temperatures.iter().min().unwrap() as i8 as u8);
+        payload.extend(self.module_temperatures.iter().map(|t| *t as i8 as u8));
+        payload.push(self.inlet_temperature as i8 as u8);
+        payload.push(cell_byte(max_cell_voltage));
+        payload.push(max_cell_no as u8 + 1);
+        payload.push(cell_byte(min_cell_voltage));
+        payload.push(min_cell_no as u8 + 1);
+        payload.push(self.fan_status);
+        payload.push(self.fan_feedback);
+        payload.push((self.aux_battery_voltage * 10.0).round() as u8);
+        payload.extend(u32_bytes(self.cumulative_charge_current * 10.0));
+        payload.extend(u32_bytes(self.cumulative_discharge_current * 10.0));
+        payload.extend(u32_bytes(self.cumulative_energy_charged * 10.0));
+        payload.extend(u32_bytes(self.cumulative_energy_discharged * 10.0));
+        payload.extend(self.operating_time.to_be_bytes());
+        payload.push(if self.charging || self.battery_current != 0.0 { 0x0D } else { 0x00 });
+        payload.extend(u16_bytes(self.battery_dc_voltage()));
+        payload.extend((self.motor_rpm as i16).to_be_bytes());
+        payload.extend((self.motor_rpm as i16).to_be_bytes());
+        payload.extend(self.isolation_resistance.to_be_bytes());
+
+        payload
+    }
+
+    /// `21 02` to `21 04` payloads, 32 cells each
+    pub fn cell_voltages_block(&self, block: usize) -> Vec<u8> {
+        let mut payload = vec![0x61, block as u8 + 2, 0xFF, 0xFF, 0xFF, 0xFF];
+        payload.extend(self.cell_voltages[block * 32..(block + 1) * 32].iter().map(|v| cell_byte(*v)));
+
+        payload
+    }
+
+    /// `21 05` payload
+    pub fn battery_health_block(&self) -> Vec<u8> {
+        let mut payload = vec![0x61, 0x05, 0xFF, 0xFF, 0xFF, 0xFF];
+        payload.extend([0x00; 5]);
+        payload.push(self.inlet_temperature as i8 as u8);
+        payload.extend([0x00; 8]);
+        payload.extend(self.heater_temperatures.iter().map(|t| *t as i8 as u8));
+        payload.extend([0x00; 5]);
+        payload.extend(u16_bytes(self.max_deterioration * 10.0));
+        payload.push(self.max_deterioration_cell);
+        payload.extend(u16_bytes(self.min_deterioration * 10.0));
+        payload.push(self.min_deterioration_cell);
+        payload.push((self.state_of_charge * 2.0).round() as u8);
+        payload.extend(u16_bytes(self.state_of_health * 10.0));
+        // cells 97 and 98 don't exist on this pack
+        payload.extend([0x00; 2]);
+        payload.extend([0x00; 7]);
+
+        payload
+    }
+}
+
+/// Supported PIDs bitmap for `01 <base>`, the last bit says whether the next bitmap exists
+fn pid_bitmap(base: u8) -> u32 {
+    let base = base as u32;
+    VMCU_PIDS
+        .iter()
+        .map(|pid| *pid as u32)
+        .filter(|pid| *pid > base)
+        .map(|pid| if pid > base + 0x20 { 1 } else { 0x8000_0000 >> (pid - base - 1) })
+        .fold(0, |bitmap, bit| bitmap | bit)
+}
+
+/// Fixed length identification string, padded with zeros
+fn padded(value: &str, len: usize) -> Vec<u8> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(len, 0x00);
+    bytes
+}
+
+fn cell_byte(voltage: f64) -> u8 {
+    (voltage / 0.02).round() as u8
+}
+
+fn u16_bytes(value: f64) -> [u8; 2] {
+    (value.round() as u16).to_be_bytes()
+}
+
+fn u32_bytes(value: f64) -> [u8; 4] {
+    (value.round() as u32).to_be_bytes()
+}
+
+struct Ecu {
+    request_id: u32,
+    response_id: u32,
+}
+
+impl Ecu {
+    /// Request and response IDs the ECU uses with 29-bit headers, 18DA<target><source> with the
+    /// tester at F1 and the low byte of the 11-bit request ID as the ECU's address
+    fn extended_ids(&self) -> (u32, u32) {
+        let address = self.request_id & 0xFF;
+        (0x18DA_00F1 | address << 8, 0x18DA_F100 | address)
+    }
+}
+
+const VMCU: Ecu = Ecu { request_id: 0x7E2, response_id: 0x7EA };
+const BMS: Ecu = Ecu { request_id: 0x7E4, response_id: 0x7EC };
+
+pub struct Emulator {
+    pack: Arc<Mutex<PackState>>,
+    animated: bool,
+    echo: bool,
+    linefeeds: bool,
+    spaces: bool,
+    headers: bool,
+    header: u32,
+    /// Top 5 bits of 29-bit headers
+    priority: u32,
+    receive_address: Option<u32>,
+    can_filter: Option<u32>,
+    can_mask: u32,
+    monitoring: bool,
+    last_command: Option<String>,
+    /// UDS session the BMS is in
+    bms_session: u8,
+}
+
+impl Emulator {
+    pub fn new(pack: Arc<Mutex<PackState>>) -> Self {
+        Self {
+            pack,
+            animated: false,
+            echo: true,
+            linefeeds: false,
+            spaces: true,
+            headers: false,
+            header: FUNCTIONAL_HEADER,
+            priority: DEFAULT_PRIORITY,
+            receive_address: None,
+            can_filter: None,
+            can_mask: 0x7FF,
+            monitoring: false,
+            last_command: None,
+            bms_session: 0x01,
+        }
+    }
+
+    pub fn set_animated(&mut self, animated: bool) {
+        self.animated = animated;
+    }
+
+    pub fn pack_state(&self) -> Arc<Mutex<PackState>> {
+        self.pack.clone()
+    }
+
+    /// Returns everything the chip would print for one command line, including echo and prompt
+    pub fn handle_command(&mut self, line: &str) -> String {
+        let mut command = line.trim().replace(' ', "").to_uppercase();
+        if command.is_empty() {
+            // a bare carriage return repeats the previous command
+            command = self.last_command.clone().unwrap_or_else(|| "ATI".to_string());
+        }
+        self.last_command = Some(command.clone());
+
+        let mut output = String::new();
+        if self.echo {
+            output.push_str(line.trim());
+            output.push('\r');
+        }
+
+        let lines = match command.strip_prefix("AT") {
+            Some(at) => self.handle_at(at),
+            None => self.handle_request(&command),
+        };
+        if self.monitoring {
+            // frames follow until the next byte arrives, see monitor_frames
+            return output;
+        }
+        let line_end = if self.linefeeds { "\r\n" } else { "\r" };
+        for line in lines {
+            output.push_str(&line);
+            output.push_str(line_end);
+        }
+        output.push_str(line_end);
+        output.push('>');
+
+        output
+    }
+
+    fn reset(&mut self) {
+        self.echo = true;
+        self.linefeeds = false;
+        self.spaces = true;
+        self.headers = false;
+        self.header = FUNCTIONAL_HEADER;
+        self.priority = DEFAULT_PRIORITY;
+        self.receive_address = None;
+        self.can_filter = None;
+        self.can_mask = 0x7FF;
+        self.monitoring = false;
+    }
+
+    pub fn is_monitoring(&self) -> bool {
+        self.monitoring
+    }
+
+    /// Any byte received while monitoring stops it
+    pub fn stop_monitoring(&mut self) -> String {
+        self.monitoring = false;
+        let line_end = if self.linefeeds { "\r\n" } else { "\r" };
+        format!("STOPPED{}{}>", line_end, line_end)
+    }
+
+    /// A round of periodic broadcasts as printed while monitoring. The IDs and layouts are made
+    /// up, they only carry pack values so there is something to look at.
+    pub fn monitor_frames(&mut self) -> String {
+        let pack = self.pack.lock().unwrap();
+        let mut frames: Vec<(u32, Vec<u8>)> = Vec::new();
+
+        let mut power = Vec::new();
+        power.extend(((pack.battery_current * 10.0).round() as i16).to_be_bytes());
+        power.extend(u16_bytes(pack.battery_dc_voltage() * 10.0));
+        power.push((pack.state_of_charge * 2.0).round() as u8);
+        frames.push((0x200, power));
+        let mut motor = Vec::new();
+        motor.extend((pack.motor_rpm as i16).to_be_bytes());
+        motor.push(pack.charging as u8);
+        frames.push((0x4F0, motor));
+        frames.push((0x5A0, pack.module_temperatures.iter().map(|t| *t as i8 as u8).collect()));
+        drop(pack);
+
+        let separator = if self.spaces { " " } else { "" };
+        let line_end = if self.linefeeds { "\r\n" } else { "\r" };
+        let mut output = String::new();
+        for (id, mut data) in frames {
+            if self.can_filter.is_some_and(|filter| id & self.can_mask != filter & self.can_mask) {
+                continue;
+            }
+            data.resize(8, 0x00);
+            let hex = data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(separator);
+            if self.headers {
+                output.push_str(&format!("{:03X}{}{}", id, separator, hex));
+            } else {
+                output.push_str(&hex);
+            }
+            output.push_str(line_end);
+        }
+        output
+    }
+
+    fn handle_at(&mut self, at: &str) -> Vec<String> {
+        let ok = vec!["OK".to_string()];
+        match at {
+            "Z" | "WS" => {
+                self.reset();
+                vec!["".to_string(), DEVICE_ID.to_string()]
+            }
+            "D" => {
+                self.reset();
+                ok
+            }
+            "I" => vec![DEVICE_ID.to_string()],
+            "@1" => vec![DEVICE_DESCRIPTION.to_string()],
+            "DP" => vec!["ISO 15765-4 (CAN 11/500)".to_string()],
+            "DPN" => vec!["A6".to_string()],
+            "RV" => vec![format!("{:.1}V", self.pack.lock().unwrap().aux_battery_voltage)],
+            "E0" | "E1" => {
+                self.echo = at == "E1";
+                ok
+            }
+            "L0" | "L1" => {
+                self.linefeeds = at == "L1";
+                ok
+            }
+            "S0" | "S1" => {
+                self.spaces = at == "S1";
+                ok
+            }
+            "H0" | "H1" => {
+                self.headers = at == "H1";
+                ok
+            }
+            "AR" | "CRA" => {
+                self.receive_address = None;
+                self.can_filter = None;
+                self.can_mask = 0x7FF;
+                ok
+            }
+            "MA" => {
+                self.monitoring = true;
+                vec![]
+            }
+            _ if at.starts_with("MR") || at.starts_with("MT") => {
+                self.monitoring = true;
+                vec![]
+            }
+            _ if at.starts_with("CF") || at.starts_with("CM") => match u32::from_str_radix(&at[2..], 16) {
+                Ok(value) => {
+                    if at.starts_with("CF") {
+                        self.can_filter = Some(value);
+                    } else {
+                        self.can_mask = value;
+                    }
+                    ok
+                }
+                Err(_) => vec!["?".to_string()],
+            },
+            "AT0" | "AT1" | "AT2" | "AL" | "NL" | "FE" | "CAF0" | "CAF1" | "M0" | "M1" => ok,
+            _ if at.starts_with("SP") || at.starts_with("TP") || at.starts_with("ST") => ok,
+            // flow control only matters on a real bus
+            _ if at.starts_with("FC") => ok,
+            _ if at.starts_with("CP") => match u32::from_str_radix(&at[2..], 16) {
+                Ok(priority) if priority <= 0x1F => {
+                    self.priority = priority;
+                    if self.header > 0x7FF {
+                        self.header = priority << 24 | (self.header & 0xFF_FFFF);
+                    }
+                    ok
+                }
+                _ => vec!["?".to_string()],
+            },
+            // 3 digits set an 11-bit ID, 6 the low 24 bits of a 29-bit one and 8 all of it
+            _ if at.starts_with("SH") => match (at.len() - 2, u32::from_str_radix(&at[2..], 16)) {
+                (3 | 8, Ok(header)) => {
+                    self.header = header;
+                    ok
+                }
+                (6, Ok(header)) => {
+                    self.header = self.priority << 24 | header;
+                    ok
+                }
+                _ => vec!["?".to_string()],
+            },
+            _ if at.starts_with("CRA") => match u32::from_str_radix(&at[3..], 16) {
+                Ok(address) => {
+                    self.receive_address = Some(address);
+                    ok
+                }
+                Err(_) => vec!["?".to_string()],
+            },
+            _ => vec!["?".to_string()],
+        }
+    }
+
+    fn handle_request(&mut self, command: &str) -> Vec<String> {
+        let request = match decode_hex(command) {
+            Some(request) if !request.is_empty() => request,
+            _ => return vec!["?".to_string()],
+        };
+
+        let mut lines = Vec::new();
+        for ecu in [VMCU, BMS] {
+            let (request_id, response_id) = if self.header > 0x7FF {
+                ecu.extended_ids()
+            } else {
+                (ecu.request_id, ecu.response_id)
+            };
+            if self.header != FUNCTIONAL_HEADER && self.header != FUNCTIONAL_EXTENDED_HEADER && self.header != request_id {
+                continue;
+            }
+            if self.receive_address.is_some_and(|a| a != response_id) {
+                continue;
+            }
+            // clearing takes the BMS a while, it answers response pending first and the result
+            // right after, both to the one request
+            if ecu.request_id == BMS.request_id && request.first() == Some(&0x14) && self.bms_session == 0x03 {
+                lines.extend(self.format_frames(response_id, &[0x7F, 0x14, 0x78]));
+            }
+            if let Some(payload) = self.ecu_response(&ecu, &request) {
+                lines.extend(self.format_frames(response_id, &payload));
+            }
+        }
+
+        if lines.is_empty() {
+            return vec!["NO DATA".to_string()];
+        }
+        lines
+    }
+
+    fn ecu_response(&mut self, ecu: &Ecu, request: &[u8]) -> Option<Vec<u8>> {
+        let mut pack = self.pack.lock().unwrap();
+        if ecu.request_id == VMCU.request_id {
+            // positive responses echo the service + 0x40 and the PID
+            let mut payload: Vec<u8> = request.iter().take(2).copied().collect();
+            payload[0] = payload[0].wrapping_add(0x40);
+            match request {
+                [0x01, base] if base % 0x20 == 0 => payload.extend(pid_bitmap(*base).to_be_bytes()),
+                [0x01, 0x01] => payload.extend([0x00, 0x07, 0xE5, 0x00]),
+                [0x01, 0x0D] => payload.push(pack.vehicle_speed.round() as u8),
+                [0x01, 0x42] => payload.extend(u16_bytes(pack.aux_battery_voltage * 1000.0)),
+                [0x01, 0x46] => payload.push((pack.ambient_temperature + 40) as u8),
+                [0x01, 0xA6] => payload.extend(u32_bytes(pack.odometer * 10.0)),
+                // 02 VIN, 04 calibration ID, 0A ECU name
+                [0x09, 0x00] => payload.extend(0x5040_0000u32.to_be_bytes()),
+                [0x09, 0x02] => {
+                    payload.push(0x01);
+                    payload.extend(VIN.as_bytes());
+                }
+                [0x09, 0x04] => {
+                    payload.push(0x01);
+                    payload.extend(padded(VMCU_CALIBRATION_ID, 16));
+                }
+                [0x09, 0x0A] => {
+                    payload.push(0x01);
+                    payload.extend(padded(VMCU_NAME, 20));
+                }
+                // stored codes, pending and permanent ones are always empty
+                [0x03] | [0x07] | [0x0A] => {
+                    let dtcs: &[u16] = if request[0] == 0x03 { &pack.stored_dtcs } else { &[] };
+                    payload.push(dtcs.len() as u8);
+                    payload.extend(dtcs.iter().flat_map(|dtc| dtc.to_be_bytes()));
+                }
+                [0x04] => pack.stored_dtcs.clear(),
+                // serviceNotSupported, only the BMS keeps UDS fault memory here
+                [0x19, ..] | [0x14, ..] => return Some(vec![0x7F, request[0], 0x11]),
+                _ => return None,
+            }
+            return Some(payload);
+        }
+
+        match request {
+            [0x21, 0x00] => Some(vec![0x61, 0x00, 0xF8, 0x00, 0x00, 0x00]),
+            [0x21, 0x01] => {
+                if self.animated {
+                    pack.jitter();
+                }
+                Some(pack.battery_info_block())
+            }
+            [0x21, block @ 0x02..=0x04] => Some(pack.cell_voltages_block((*block - 2) as usize)),
+            [0x21, 0x05] => Some(pack.battery_health_block()),
+            [0x22, 0xF1, 0x87] => Some([&[0x62, 0xF1, 0x87], BMS_PART_NUMBER.as_bytes()].concat()),
+            [0x22, 0xF1, 0x89] => Some([&[0x62, 0xF1, 0x89], BMS_SOFTWARE_VERSION.as_bytes()].concat()),
+            // requestOutOfRange, the BMS doesn't know the VIN
+            [0x22, ..] => Some(vec![0x7F, 0x22, 0x31]),
+            [0x10, session @ (0x01 | 0x03)] => {
+                self.bms_session = *session;
+                // P2 50ms, P2* 5s
+                Some(vec![0x50, *session, 0x00, 0x32, 0x01, 0xF4])
+            }
+            [0x3E, 0x00] => Some(vec![0x7E, 0x00]),
+            [0x19, 0x02, mask] => {
+                let mut payload = vec![0x59, 0x02, 0xFF];
+                for (dtc, status) in pack.bms_dtcs.iter().filter(|(_, status)| status & mask != 0) {
+                    payload.extend(&dtc.to_be_bytes()[1..]);
+                    payload.push(*status);
+                }
+                Some(payload)
+            }
+            // serviceNotSupportedInActiveSession
+            [0x14, ..] if self.bms_session != 0x03 => Some(vec![0x7F, 0x14, 0x7F]),
+            [0x14, 0xFF, 0xFF, 0xFF] => {
+                pack.bms_dtcs.clear();
+                Some(vec![0x54])
+            }
+            _ => None,
+        }
+    }
+
+    /// Splits a payload into ISO-TP frames printed the way the chip does for the current settings
+    fn format_frames(&self, response_id: u32, payload: &[u8]) -> Vec<String> {
+        let separator = if self.spaces { " " } else { "" };
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(separator);
+
+        if !self.headers {
+            if payload.len() <= 7 {
+                return vec![hex(payload)];
+            }
+            let mut lines = vec![format!("{:03X}", payload.len())];
+            lines.push(format!("0:{}{}", separator, hex(&payload[..6])));
+            for (i, chunk) in payload[6..].chunks(7).enumerate() {
+                lines.push(format!("{:X}:{}{}", (i + 1) % 16, separator, hex(chunk)));
+            }
+            return lines;
+        }
+
+        let frames = if payload.len() <= 7 {
+            let mut frame = vec![payload.len() as u8];
+            frame.extend(payload);
+            vec![frame]
+        } else {
+            let mut frames = vec![];
+            let mut first = vec![0x10 | ((payload.len() >> 8) as u8 & 0x0F), payload.len() as u8];
+            first.extend(&payload[..6]);
+            frames.push(first);
+            for (i, chunk) in payload[6..].chunks(7).enumerate() {
+                let mut frame = vec![0x20 | ((i + 1) % 16) as u8];
+                frame.extend(chunk);
+                frames.push(frame);
+            }
+            frames
+        };
+
+        // 29-bit IDs are printed as four bytes
+        let header = if response_id > 0x7FF {
+            hex(&response_id.to_be_bytes())
+        } else {
+            format!("{:03X}", response_id)
+        };
+        frames
+            .into_iter()
+            .map(|mut frame| {
+                frame.resize(8, 0x00);
+                format!("{}{}{}", header, separator, hex(&frame))
+            })
+            .collect()
+    }
+}
+
+fn decode_hex(command: &str) -> Option<Vec<u8>> {
+    if command.len() % 2 == 1 {
+        return None;
+    }
+    (0..command.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&command[i..i + 2], 16).ok())
+        .collect()
+}
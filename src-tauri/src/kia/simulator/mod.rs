@@ -0,0 +1,104 @@
+mod emulator;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use error_stack::Report;
+
+use crate::elm327::transport::{self, Transport};
+pub use emulator::PackState;
+use emulator::Emulator;
+
+/// In-process transport that behaves like an ELM327 plugged into a Soul EV
+pub struct Simulator {
+    emulator: Emulator,
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    connected: bool,
+    last_broadcast: Instant,
+}
+
+impl Simulator {
+    const BROADCAST_INTERVAL: Duration = Duration::from_millis(100);
+    /// Vehicle profile the simulated BMS answers like
+    pub const PROFILE: &'static str = "kia_soul_ev";
+
+    pub fn new(pack: PackState) -> Self {
+        Self {
+            emulator: Emulator::new(Arc::new(Mutex::new(pack))),
+            input: Vec::new(),
+            output: VecDeque::new(),
+            connected: false,
+            last_broadcast: Instant::now(),
+        }
+    }
+
+    /// Simulator for the demo mode, with a pack that changes a bit on every read
+    pub fn demo() -> Self {
+        let mut simulator = Self::new(PackState::default());
+        simulator.emulator.set_animated(true);
+        simulator
+    }
+
+    /// Shared handle to the simulated pack, changes are visible in the next response
+    pub fn pack_state(&self) -> Arc<Mutex<PackState>> {
+        self.emulator.pack_state()
+    }
+}
+
+impl Transport for Simulator {
+    fn init(&mut self) -> transport::Result<()> {
+        self.input.clear();
+        self.output.clear();
+        self.connected = true;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> transport::Result<()> {
+        if !self.connected {
+            return Err(Report::new(transport::Error::NotConnected));
+        }
+
+        for b in data {
+            if self.emulator.is_monitoring() {
+                self.output.extend(self.emulator.stop_monitoring().as_bytes());
+                continue;
+            }
+            match b {
+                b'\r' => {
+                    let line = String::from_utf8_lossy(&self.input).to_string();
+                    self.input.clear();
+                    self.output.extend(self.emulator.handle_command(&line).as_bytes());
+                }
+                b'\n' => {}
+                _ => self.input.push(*b),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> transport::Result<usize> {
+        if !self.connected {
+            return Err(Report::new(transport::Error::NotConnected));
+        }
+
+        if self.emulator.is_monitoring() && self.last_broadcast.elapsed() >= Self::BROADCAST_INTERVAL {
+            self.output.extend(self.emulator.monitor_frames().as_bytes());
+            self.last_broadcast = Instant::now();
+        }
+
+        let len = data.len().min(self.output.len());
+        for (i, b) in self.output.drain(..len).enumerate() {
+            data[i] = b;
+        }
+        Ok(len)
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn description(&self) -> Option<String> {
+        Some("simulator".to_string())
+    }
+}
@@ -4,12 +4,27 @@
 use std::sync;
 mod elm327;
 mod kia;
+mod obd2;
+mod uds;
+mod worker;
+mod scheduler;
 mod error;
 mod command;
 use command::*;
 
 struct AppState {
-    kia: Option<kia::Kia>,
+    /// Owns the connection to the car
+    worker: Option<worker::Worker>,
+    capture_settings: elm327::capture::CaptureSettings,
+    can_monitor: Option<CanMonitor>,
+    /// Token issued for clearing fault codes and when
+    dtc_clear_token: Option<(String, std::time::Instant)>,
+    /// Lets write requests through the read-only guard
+    expert_mode: bool,
+    low_voltage_settings: kia::LowVoltageSettings,
+    /// Reads the car in the background while polling is on
+    scheduler: Option<scheduler::Scheduler>,
+    polling_settings: scheduler::PollingSettings,
 }
 
 
@@ -19,13 +34,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(tauri::Builder::default()
         .manage(sync::Mutex::new(AppState {
-            kia: None,
+            worker: None,
+            capture_settings: Default::default(),
+            can_monitor: None,
+            dtc_clear_token: None,
+            expert_mode: false,
+            low_voltage_settings: Default::default(),
+            scheduler: None,
+            polling_settings: Default::default(),
         }))
         .invoke_handler(tauri::generate_handler![
             connect,
             disconnect,
-            get_car_info,
+            list_vehicle_profiles,
+            start_polling,
+            stop_polling,
+            get_polling_settings,
+            set_polling_settings,
             list_serial_devices,
+            get_capture_settings,
+            set_capture_settings,
+            start_can_monitor,
+            stop_can_monitor,
+            get_obd2_values,
+            get_dtcs,
+            request_dtc_clear_token,
+            clear_dtcs,
+            get_expert_mode,
+            set_expert_mode,
+            get_low_voltage_settings,
+            set_low_voltage_settings,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application"))
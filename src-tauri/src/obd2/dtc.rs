@@ -0,0 +1,171 @@
+use error_stack::Report;
+use serde::Serialize;
+
+use crate::elm327::{self, Command};
+use crate::uds;
+use super::positive_payloads;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DtcKind {
+    /// Mode 03
+    Stored,
+    /// Mode 07
+    Pending,
+    /// Mode 0A
+    Permanent,
+    /// UDS `19 02`
+    Manufacturer,
+}
+
+/// UDS DTC status byte, ISO 14229-1 D.2
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct DtcStatus {
+    pub test_failed: bool,
+    pub test_failed_this_operation_cycle: bool,
+    pub pending: bool,
+    pub confirmed: bool,
+    pub test_not_completed_since_last_clear: bool,
+    pub test_failed_since_last_clear: bool,
+    pub test_not_completed_this_operation_cycle: bool,
+    pub warning_indicator_requested: bool,
+}
+
+impl From<u8> for DtcStatus {
+    fn from(status: u8) -> Self {
+        let bit = |n: u8| status & (1 << n) != 0;
+        Self {
+            test_failed: bit(0),
+            test_failed_this_operation_cycle: bit(1),
+            pending: bit(2),
+            confirmed: bit(3),
+            test_not_completed_since_last_clear: bit(4),
+            test_failed_since_last_clear: bit(5),
+            test_not_completed_this_operation_cycle: bit(6),
+            warning_indicator_requested: bit(7),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Dtc {
+    /// e.g. `P0A7F`
+    pub code: String,
+    pub kind: DtcKind,
+    /// Name or response ID of the ECU that reported it
+    pub ecu: Option<String>,
+    /// UDS failure type byte
+    pub failure_type: Option<u8>,
+    pub status: Option<DtcStatus>,
+}
+
+/// Decodes the two byte SAE J2012 form: system letter in the top 2 bits, then 14 bits of digits
+pub fn decode_code(high: u8, low: u8) -> String {
+    let system = ['P', 'C', 'B', 'U'][(high >> 6) as usize];
+    format!("{}{}{:X}{:02X}", system, (high >> 4) & 0x03, high & 0x0F, low)
+}
+
+/// Modes 03, 07 and 0A
+pub struct ReadDtcsCommand(pub DtcKind);
+
+impl ReadDtcsCommand {
+    fn service(&self) -> u8 {
+        match self.0 {
+            DtcKind::Pending => 0x07,
+            DtcKind::Permanent => 0x0A,
+            _ => 0x03,
+        }
+    }
+}
+
+impl Command for ReadDtcsCommand {
+    type Response = Vec<Dtc>;
+
+    fn serial_command(&self) -> String {
+        format!("{:02X}", self.service())
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        let mut dtcs = Vec::new();
+        for payload in positive_payloads(&response, &[self.service()])? {
+            // 43 <count> then two bytes per code, on CAN
+            let codes = payload.data.get(2..).unwrap_or_default();
+            dtcs.extend(codes
+                .chunks_exact(2)
+                .filter(|code| code != &[0x00, 0x00])
+                .map(|code| Dtc {
+                    code: decode_code(code[0], code[1]),
+                    kind: self.0,
+                    ecu: payload.ecu.map(|ecu| format!("{:03X}", ecu)),
+                    failure_type: None,
+                    status: None,
+                }));
+        }
+        Ok(dtcs)
+    }
+}
+
+/// Mode 04, clears stored and pending codes of every ECU that listens to OBD requests
+pub struct ClearDtcsCommand();
+
+impl Command for ClearDtcsCommand {
+    type Response = ();
+
+    fn serial_command(&self) -> String {
+        "04".to_string()
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        positive_payloads(&response, &[0x04])?;
+        Ok(())
+    }
+}
+
+/// UDS `19 02` reportDTCByStatusMask
+pub struct ReportDtcsByStatusMask {
+    pub mask: u8,
+    pub ecu: &'static str,
+}
+
+impl uds::Request for ReportDtcsByStatusMask {
+    type Response = Vec<Dtc>;
+    const SERVICE: u8 = 0x19;
+
+    fn parameters(&self) -> Vec<u8> {
+        vec![0x02, self.mask]
+    }
+
+    fn parse_response(&self, data: &[u8]) -> uds::Result<Self::Response> {
+        match data {
+            // 02 <availability mask> then DTC high, middle, failure type and status
+            [0x02, _, records @ ..] => Ok(records
+                .chunks_exact(4)
+                .map(|record| Dtc {
+                    code: decode_code(record[0], record[1]),
+                    kind: DtcKind::Manufacturer,
+                    ecu: Some(self.ecu.to_string()),
+                    failure_type: Some(record[2]),
+                    status: Some(DtcStatus::from(record[3])),
+                })
+                .collect()),
+            _ => Err(Report::new(uds::Error::UnexpectedResponse)
+                .attach_printable(format!("unexpected answer {:02X?} from {}", data, self.ecu))),
+        }
+    }
+}
+
+/// UDS `14 FF FF FF` ClearDiagnosticInformation for all groups
+pub struct ClearDiagnosticInformation();
+
+impl uds::Request for ClearDiagnosticInformation {
+    type Response = ();
+    const SERVICE: u8 = 0x14;
+
+    fn parameters(&self) -> Vec<u8> {
+        vec![0xFF, 0xFF, 0xFF]
+    }
+
+    fn parse_response(&self, _data: &[u8]) -> uds::Result<Self::Response> {
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+// Standard OBD-II services, independent of the car model
+pub mod mode01;
+pub mod mode09;
+pub mod dtc;
+
+use error_stack::{Report, ResultExt};
+
+use crate::elm327::{self, isotp};
+
+/// Payloads of every ECU that answered, checked to echo `service + 0x40` and the first request byte
+fn positive_responses(response: &str, request: &[u8]) -> elm327::Result<Vec<Vec<u8>>> {
+    Ok(positive_payloads(response, request)?.into_iter().map(|p| p.data).collect())
+}
+
+/// Same as `positive_responses`, keeping track of which ECU sent each payload
+fn positive_payloads(response: &str, request: &[u8]) -> elm327::Result<Vec<isotp::Payload>> {
+    let payloads = isotp::reassemble(response).change_context(elm327::Error::Other)?;
+    let positive: Vec<isotp::Payload> = payloads
+        .into_iter()
+        .filter(|p| p.data.first() == Some(&(request[0] + 0x40)) && p.data[1..].starts_with(&request[1..]))
+        .collect();
+
+    if positive.is_empty() {
+        return Err(Report::new(elm327::Error::Other)
+            .attach_printable(format!("no positive response to {:02X?} in {:?}", request, response)));
+    }
+    Ok(positive)
+}
@@ -0,0 +1,131 @@
+use error_stack::Report;
+use serde::Serialize;
+
+use crate::elm327::{self, Command, Elm327};
+use super::positive_responses;
+
+/// PIDs `00`, `20`, `40`... answer with a bitmap of the 32 PIDs that follow them
+pub struct SupportedPidsCommand(pub u8);
+
+impl Command for SupportedPidsCommand {
+    type Response = Vec<u8>;
+
+    fn serial_command(&self) -> String {
+        format!("01 {:02X}", self.0)
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        let mut bitmap = 0u32;
+        // every ECU has its own bitmap, any of them answering is enough
+        for payload in positive_responses(&response, &[0x01, self.0])? {
+            let bytes = payload.get(2..6).ok_or_else(|| {
+                Report::new(elm327::Error::Other).attach_printable(format!("short bitmap {:02X?}", payload))
+            })?;
+            bitmap |= u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok((0..32)
+            .filter(|bit| bitmap & (0x8000_0000 >> bit) != 0)
+            .filter_map(|bit| self.0.checked_add(bit as u8 + 1))
+            .collect())
+    }
+}
+
+/// Follows the chain of bitmaps for as long as each one says the next exists
+pub fn supported_pids(device: &mut Elm327) -> elm327::Result<Vec<u8>> {
+    let mut supported = Vec::new();
+    let mut base = 0x00u8;
+    loop {
+        let pids = device.execute_command(SupportedPidsCommand(base))?;
+        let next = base.checked_add(0x20);
+        let has_next = next.is_some_and(|next| pids.contains(&next));
+        supported.extend(pids.into_iter().filter(|pid| pid % 0x20 != 0));
+        match next {
+            Some(next) if has_next => base = next,
+            _ => return Ok(supported),
+        }
+    }
+}
+
+pub struct PidDefinition {
+    pub pid: u8,
+    pub name: &'static str,
+    pub unit: &'static str,
+    len: usize,
+    decode: fn(&[u8]) -> f64,
+}
+
+fn word(data: &[u8]) -> f64 {
+    ((data[0] as u32) << 8 | data[1] as u32) as f64
+}
+
+fn percent(data: &[u8]) -> f64 {
+    data[0] as f64 * 100.0 / 255.0
+}
+
+fn temperature(data: &[u8]) -> f64 {
+    data[0] as f64 - 40.0
+}
+
+/// Mode 01 PIDs with a numeric value, formulas from SAE J1979
+pub const PIDS: &[PidDefinition] = &[
+    PidDefinition { pid: 0x04, name: "engine_load", unit: "%", len: 1, decode: percent },
+    PidDefinition { pid: 0x05, name: "coolant_temperature", unit: "°C", len: 1, decode: temperature },
+    PidDefinition { pid: 0x0C, name: "engine_speed", unit: "rpm", len: 2, decode: |d| word(d) / 4.0 },
+    PidDefinition { pid: 0x0D, name: "vehicle_speed", unit: "km/h", len: 1, decode: |d| d[0] as f64 },
+    PidDefinition { pid: 0x0F, name: "intake_air_temperature", unit: "°C", len: 1, decode: temperature },
+    PidDefinition { pid: 0x11, name: "throttle_position", unit: "%", len: 1, decode: percent },
+    PidDefinition { pid: 0x1F, name: "run_time", unit: "s", len: 2, decode: word },
+    PidDefinition { pid: 0x21, name: "distance_with_mil_on", unit: "km", len: 2, decode: word },
+    PidDefinition { pid: 0x2F, name: "fuel_level", unit: "%", len: 1, decode: percent },
+    PidDefinition { pid: 0x31, name: "distance_since_codes_cleared", unit: "km", len: 2, decode: word },
+    PidDefinition { pid: 0x33, name: "barometric_pressure", unit: "kPa", len: 1, decode: |d| d[0] as f64 },
+    PidDefinition { pid: 0x42, name: "control_module_voltage", unit: "V", len: 2, decode: |d| word(d) / 1000.0 },
+    PidDefinition { pid: 0x46, name: "ambient_air_temperature", unit: "°C", len: 1, decode: temperature },
+    PidDefinition { pid: 0x4D, name: "time_with_mil_on", unit: "min", len: 2, decode: word },
+    PidDefinition { pid: 0x5B, name: "hybrid_battery_remaining_life", unit: "%", len: 1, decode: percent },
+    PidDefinition {
+        pid: 0xA6,
+        name: "odometer",
+        unit: "km",
+        len: 4,
+        decode: |d| u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as f64 / 10.0,
+    },
+];
+
+pub fn definition(pid: u8) -> Option<&'static PidDefinition> {
+    PIDS.iter().find(|definition| definition.pid == pid)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PidValue {
+    pub pid: u8,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub value: f64,
+}
+
+pub struct PidCommand(pub &'static PidDefinition);
+
+impl Command for PidCommand {
+    type Response = PidValue;
+
+    fn serial_command(&self) -> String {
+        format!("01 {:02X}", self.0.pid)
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        let payloads = positive_responses(&response, &[0x01, self.0.pid])?;
+        let data = payloads[0].get(2..2 + self.0.len).ok_or_else(|| {
+            Report::new(elm327::Error::Other)
+                .attach_printable(format!("PID {:02X} answered {:02X?}", self.0.pid, payloads[0]))
+        })?;
+
+        Ok(PidValue {
+            pid: self.0.pid,
+            name: self.0.name,
+            unit: self.0.unit,
+            value: (self.0.decode)(data),
+        })
+    }
+}
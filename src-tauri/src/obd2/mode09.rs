@@ -0,0 +1,90 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use error_stack::Report;
+
+use crate::elm327::{self, Command};
+use super::positive_responses;
+
+/// Printable characters of an identification string, without the padding ECUs fill it with
+fn ascii(data: &[u8]) -> String {
+    data.iter()
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|b| *b as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// `09 02`
+pub struct VinCommand();
+
+impl Command for VinCommand {
+    type Response = String;
+
+    fn serial_command(&self) -> String {
+        "09 02".to_string()
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        let payloads = positive_responses(&response, &[0x09, 0x02])?;
+        // 49 02 <number of data items> <17 characters>
+        let vin = payloads[0].get(3..).map(ascii).unwrap_or_default();
+        if vin.len() != 17 {
+            return Err(Report::new(elm327::Error::Other).attach_printable(format!("invalid VIN {:?}", vin)));
+        }
+        Ok(vin)
+    }
+}
+
+/// `09 04`, every ECU reports one or more 16 character calibration IDs
+pub struct CalibrationIdsCommand();
+
+impl Command for CalibrationIdsCommand {
+    type Response = Vec<String>;
+
+    fn serial_command(&self) -> String {
+        "09 04".to_string()
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        Ok(positive_responses(&response, &[0x09, 0x04])?
+            .iter()
+            .flat_map(|payload| payload.get(3..).unwrap_or_default().chunks(16).map(ascii).collect::<Vec<_>>())
+            .filter(|id| !id.is_empty())
+            .collect())
+    }
+}
+
+/// `09 0A`, 20 characters per ECU
+pub struct EcuNamesCommand();
+
+impl Command for EcuNamesCommand {
+    type Response = Vec<String>;
+
+    fn serial_command(&self) -> String {
+        "09 0A".to_string()
+    }
+
+    fn parse_result(&self, response: String) -> elm327::Result<Self::Response> {
+        Ok(positive_responses(&response, &[0x09, 0x0A])?
+            .iter()
+            .map(|payload| ascii(payload.get(3..).unwrap_or_default()))
+            .filter(|name| !name.is_empty())
+            .collect())
+    }
+}
+
+/// Model year from the 10th VIN character. The codes repeat every 30 years, the latest year
+/// that isn't in the future is picked.
+pub fn model_year(vin: &str) -> Option<u16> {
+    const CODES: &str = "ABCDEFGHJKLMNPRSTVWXY123456789";
+    let code = vin.chars().nth(9)?.to_ascii_uppercase();
+    let offset = CODES.find(code)? as u16;
+
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    // a model year starts selling the year before
+    let latest = 1970 + (seconds / 31_556_952) as u16 + 1;
+    (0..)
+        .map(|cycle| 1980 + offset + cycle * 30)
+        .take_while(|year| *year <= latest)
+        .last()
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::{self, mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::AppState;
+use crate::error::CommandError;
+use crate::kia::{Sample, SignalGroup};
+use crate::worker::Priority;
+
+/// How often each signal group is read, in milliseconds. Groups without an interval aren't read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PollingSettings {
+    pub intervals: HashMap<SignalGroup, u64>,
+}
+
+impl Default for PollingSettings {
+    fn default() -> Self {
+        Self {
+            intervals: HashMap::from([
+                (SignalGroup::Pack, 1000),
+                (SignalGroup::CellVoltages, 5000),
+                (SignalGroup::Temperatures, 10000),
+                (SignalGroup::Health, 60000),
+            ]),
+        }
+    }
+}
+
+/// Payload of the `car_sample_error` event
+#[derive(Serialize, Clone)]
+struct SampleError {
+    group: SignalGroup,
+    error: CommandError,
+}
+
+struct Schedule {
+    settings: PollingSettings,
+    stopped: bool,
+}
+
+/// Reads the signal groups on the connection's worker at their own intervals and emits the
+/// results in `car_sample` events, failed reads in `car_sample_error` events. A failed read
+/// doesn't stop the polling, it goes on with the next connection after a reconnect.
+pub struct Scheduler {
+    schedule: Arc<(Mutex<Schedule>, Condvar)>,
+}
+
+impl Scheduler {
+    /// Keeps a busy car from being asked for the same group over and over
+    const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn spawn(settings: PollingSettings, app_handle: AppHandle) -> Self {
+        let schedule = Arc::new((Mutex::new(Schedule { settings, stopped: false }), Condvar::new()));
+        {
+            let schedule = schedule.clone();
+            thread::spawn(move || Self::run(app_handle, schedule));
+        }
+        Self { schedule }
+    }
+
+    pub fn set_settings(&self, settings: PollingSettings) {
+        let (schedule, changed) = &*self.schedule;
+        schedule.lock().unwrap().settings = settings;
+        changed.notify_one();
+    }
+
+    fn run(app_handle: AppHandle, schedule: Arc<(Mutex<Schedule>, Condvar)>) {
+        let (schedule, changed) = &*schedule;
+        let mut next_reads: HashMap<SignalGroup, Instant> = HashMap::new();
+        loop {
+            let (group, interval) = {
+                let mut schedule = schedule.lock().unwrap();
+                loop {
+                    if schedule.stopped {
+                        debug!("Polling stopped");
+                        return;
+                    }
+                    let now = Instant::now();
+                    // the group that waited the longest goes first, new ones are read right away
+                    let next = schedule.settings.intervals
+                        .iter()
+                        .map(|(group, interval)| (*group, *interval, next_reads.get(group).copied().unwrap_or(now)))
+                        .min_by_key(|(_, _, next_read)| *next_read);
+                    schedule = match next {
+                        Some((group, interval, next_read)) if next_read <= now => {
+                            break (group, Duration::from_millis(interval).max(Self::MIN_INTERVAL));
+                        }
+                        Some((_, _, next_read)) => changed.wait_timeout(schedule, next_read - now).unwrap().0,
+                        None => changed.wait(schedule).unwrap(),
+                    };
+                }
+            };
+            next_reads.insert(group, Instant::now() + interval);
+
+            // an answer that came in after the polling was stopped is dropped as well
+            let Some(reply) = Self::submit(&app_handle, group) else {
+                continue;
+            };
+            let Ok(result) = reply.recv() else {
+                continue;
+            };
+            if schedule.lock().unwrap().stopped {
+                continue;
+            }
+            let emitted = match result {
+                Ok(sample) => app_handle.emit_all("car_sample", sample),
+                Err(error) => {
+                    debug!("Reading {:?} failed: {:?}", group, error);
+                    app_handle.emit_all("car_sample_error", SampleError { group, error })
+                }
+            };
+            if let Err(e) = emitted {
+                warn!("Can't emit a car sample: {}", e);
+            }
+        }
+    }
+
+    /// `None` while there is nothing to read from
+    fn submit(app_handle: &AppHandle, group: SignalGroup) -> Option<mpsc::Receiver<Result<Sample, CommandError>>> {
+        let app_state = app_handle.state::<sync::Mutex<AppState>>();
+        let app_state = app_state.lock().unwrap();
+        // the monitor has the connection, polling picks up again once it stops
+        if app_state.can_monitor.is_some() {
+            return None;
+        }
+        let worker = app_state.worker.as_ref()?;
+        Some(worker.submit(Priority::Normal, move |kia| kia.read_signals(group).map_err(CommandError::from)))
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let (schedule, changed) = &*self.schedule;
+        schedule.lock().unwrap().stopped = true;
+        changed.notify_one();
+    }
+}
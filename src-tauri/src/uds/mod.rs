@@ -0,0 +1,488 @@
+// UDS (ISO 14229-1) services on top of the ELM327. Requests go to one ECU at a time, the caller
+// points the adapter at it (AT SH / AT CRA) first.
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+use error_stack::{Report, ResultExt};
+use log::debug;
+
+use crate::elm327::{self, isotp, Elm327};
+
+pub type Result<T> = error_stack::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("Adapter error: {0}")]
+    Adapter(elm327::Error),
+    #[error("Service {service:02X} rejected: {code}")]
+    Rejected { service: u8, code: NegativeResponseCode },
+    #[error("Service {0:02X} still pending after the ECU's time limit")]
+    PendingTimeout(u8),
+    #[error("Unexpected response")]
+    UnexpectedResponse,
+}
+
+impl Error {
+    fn from_elm327(e: Report<elm327::Error>) -> Report<Error> {
+        let error = *e.current_context();
+        e.change_context(Error::Adapter(error))
+    }
+}
+
+/// Reason an ECU gives in a `7F <service> <code>` negative response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NegativeResponseCode {
+    GeneralReject,
+    ServiceNotSupported,
+    SubFunctionNotSupported,
+    IncorrectMessageLength,
+    ResponseTooLong,
+    BusyRepeatRequest,
+    ConditionsNotCorrect,
+    RequestSequenceError,
+    RequestOutOfRange,
+    SecurityAccessDenied,
+    InvalidKey,
+    ExceededNumberOfAttempts,
+    RequiredTimeDelayNotExpired,
+    ResponsePending,
+    SubFunctionNotSupportedInActiveSession,
+    ServiceNotSupportedInActiveSession,
+    Other(u8),
+}
+
+impl From<u8> for NegativeResponseCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x10 => Self::GeneralReject,
+            0x11 => Self::ServiceNotSupported,
+            0x12 => Self::SubFunctionNotSupported,
+            0x13 => Self::IncorrectMessageLength,
+            0x14 => Self::ResponseTooLong,
+            0x21 => Self::BusyRepeatRequest,
+            0x22 => Self::ConditionsNotCorrect,
+            0x24 => Self::RequestSequenceError,
+            0x31 => Self::RequestOutOfRange,
+            0x33 => Self::SecurityAccessDenied,
+            0x35 => Self::InvalidKey,
+            0x36 => Self::ExceededNumberOfAttempts,
+            0x37 => Self::RequiredTimeDelayNotExpired,
+            0x78 => Self::ResponsePending,
+            0x7E => Self::SubFunctionNotSupportedInActiveSession,
+            0x7F => Self::ServiceNotSupportedInActiveSession,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for NegativeResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::GeneralReject => "general reject",
+            Self::ServiceNotSupported => "service not supported",
+            Self::SubFunctionNotSupported => "sub-function not supported",
+            Self::IncorrectMessageLength => "incorrect message length or format",
+            Self::ResponseTooLong => "response too long",
+            Self::BusyRepeatRequest => "busy, repeat request",
+            Self::ConditionsNotCorrect => "conditions not correct",
+            Self::RequestSequenceError => "request sequence error",
+            Self::RequestOutOfRange => "request out of range",
+            Self::SecurityAccessDenied => "security access denied",
+            Self::InvalidKey => "invalid key",
+            Self::ExceededNumberOfAttempts => "exceeded number of attempts",
+            Self::RequiredTimeDelayNotExpired => "required time delay not expired",
+            Self::ResponsePending => "response pending",
+            Self::SubFunctionNotSupportedInActiveSession => "sub-function not supported in active session",
+            Self::ServiceNotSupportedInActiveSession => "service not supported in active session",
+            Self::Other(code) => return write!(f, "code {:02X}", code),
+        };
+        f.write_str(description)
+    }
+}
+
+pub trait Request {
+    type Response;
+    const SERVICE: u8;
+    /// Request bytes after the service ID
+    fn parameters(&self) -> Vec<u8>;
+    /// Parses a positive response, `data` starts after the response service ID
+    fn parse_response(&self, data: &[u8]) -> Result<Self::Response>;
+}
+
+/// How long the ECU may keep a request pending with `7F xx 78`, P2* of ISO 14229-2. Every
+/// further pending answer starts it again.
+const RESPONSE_PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Gives up on an ECU that keeps sending pending answers
+const RESPONSE_PENDING_LIMIT: Duration = Duration::from_secs(60);
+const BUSY_RETRIES: usize = 3;
+
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Positive(Vec<u8>),
+    Negative(NegativeResponseCode),
+}
+
+/// Sends `request` and waits out response-pending and busy answers
+pub fn execute<R: Request>(device: &mut Elm327, request: &R) -> Result<R::Response> {
+    let mut message = vec![R::SERVICE];
+    message.extend(request.parameters());
+    let command = message.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+
+    let mut busy_retries = 0;
+    loop {
+        let response = device.serial_cmd(&command).map_err(Error::from_elm327)?;
+        let mut answer = find_answer::<R>(&response)
+            .attach_printable_lazy(|| format!("response to {}", command))?
+            .ok_or_else(|| {
+                Report::new(Error::UnexpectedResponse).attach_printable(format!("answer {:?} to {}", response, command))
+            })?;
+
+        // the final answer follows the pending one in the same response when it comes quickly
+        // enough, otherwise the ECU sends it on its own later. Asking again would start the
+        // request a second time.
+        let started = Instant::now();
+        while answer == Answer::Negative(NegativeResponseCode::ResponsePending) {
+            if started.elapsed() >= RESPONSE_PENDING_LIMIT {
+                return Err(Report::new(Error::PendingTimeout(R::SERVICE)));
+            }
+            debug!("{} pending, waiting for the answer", command);
+            // further pending answers are waited out on the same monitor, restarting it could
+            // miss the final answer
+            let mut pending_again = false;
+            let response = device
+                .listen(RESPONSE_PENDING_TIMEOUT, |response| match find_answer::<R>(response) {
+                    Ok(Some(Answer::Negative(NegativeResponseCode::ResponsePending))) => {
+                        pending_again = true;
+                        false
+                    }
+                    Ok(Some(_)) => true,
+                    _ => false,
+                })
+                .map_err(Error::from_elm327)?;
+            answer = match response {
+                Some(response) => find_answer::<R>(&response)?.ok_or_else(|| Report::new(Error::UnexpectedResponse))?,
+                None if pending_again => continue,
+                None => return Err(Report::new(Error::PendingTimeout(R::SERVICE))),
+            };
+        }
+
+        match answer {
+            Answer::Positive(data) => return request.parse_response(&data),
+            Answer::Negative(NegativeResponseCode::BusyRepeatRequest) if busy_retries < BUSY_RETRIES => {
+                debug!("ECU busy, repeating {}", command);
+                busy_retries += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            Answer::Negative(code) => return Err(Report::new(Error::Rejected { service: R::SERVICE, code })),
+        }
+    }
+}
+
+/// The last answer to `R` in a response, `None` while there is none or it is still incomplete
+fn find_answer<R: Request>(response: &str) -> Result<Option<Answer>> {
+    let payloads = isotp::reassemble(response).change_context(Error::UnexpectedResponse)?;
+    Ok(payloads.iter().rev().find_map(|payload| match payload.data.as_slice() {
+        [service, data @ ..] if *service == R::SERVICE + 0x40 => Some(Answer::Positive(data.to_vec())),
+        [0x7F, service, code] if *service == R::SERVICE => Some(Answer::Negative(NegativeResponseCode::from(*code))),
+        _ => None,
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionType {
+    Default = 0x01,
+    Extended = 0x03,
+}
+
+/// `10` DiagnosticSessionControl
+pub struct DiagnosticSessionControl(pub SessionType);
+
+impl Request for DiagnosticSessionControl {
+    type Response = ();
+    const SERVICE: u8 = 0x10;
+
+    fn parameters(&self) -> Vec<u8> {
+        vec![self.0 as u8]
+    }
+
+    fn parse_response(&self, data: &[u8]) -> Result<Self::Response> {
+        // the session echo is followed by the P2 timings, which we don't need
+        match data.first() {
+            Some(session) if *session == self.0 as u8 => Ok(()),
+            _ => Err(Report::new(Error::UnexpectedResponse).attach_printable(format!("session answer {:02X?}", data))),
+        }
+    }
+}
+
+/// `3E 00` TesterPresent, with a response so a dropped session shows up
+pub struct TesterPresent();
+
+impl Request for TesterPresent {
+    type Response = ();
+    const SERVICE: u8 = 0x3E;
+
+    fn parameters(&self) -> Vec<u8> {
+        vec![0x00]
+    }
+
+    fn parse_response(&self, _data: &[u8]) -> Result<Self::Response> {
+        Ok(())
+    }
+}
+
+/// `22` ReadDataByIdentifier
+pub struct ReadDataByIdentifier(pub u16);
+
+impl Request for ReadDataByIdentifier {
+    type Response = Vec<u8>;
+    const SERVICE: u8 = 0x22;
+
+    fn parameters(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn parse_response(&self, data: &[u8]) -> Result<Self::Response> {
+        match data {
+            [high, low, data @ ..] if u16::from_be_bytes([*high, *low]) == self.0 => Ok(data.to_vec()),
+            _ => Err(Report::new(Error::UnexpectedResponse)
+                .attach_printable(format!("answer {:02X?} to identifier {:04X}", data, self.0))),
+        }
+    }
+}
+
+/// An open non-default session. The ECU drops back to the default session when it doesn't hear
+/// from the tester for 5 seconds (S3), `keep_alive` has to be called more often than that.
+pub struct Session {
+    kind: SessionType,
+    last_activity: Instant,
+}
+
+impl Session {
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(2);
+
+    pub fn open(device: &mut Elm327, kind: SessionType) -> Result<Self> {
+        execute(device, &DiagnosticSessionControl(kind))?;
+        debug!("Opened {:?} session", kind);
+        Ok(Self { kind, last_activity: Instant::now() })
+    }
+
+    pub fn kind(&self) -> SessionType {
+        self.kind
+    }
+
+    pub fn execute<R: Request>(&mut self, device: &mut Elm327, request: &R) -> Result<R::Response> {
+        let result = execute(device, request);
+        self.last_activity = Instant::now();
+        result
+    }
+
+    /// Whether the session needs a tester present to stay open
+    pub fn is_idle(&self) -> bool {
+        self.last_activity.elapsed() >= Self::KEEP_ALIVE_INTERVAL
+    }
+
+    pub fn keep_alive(&mut self, device: &mut Elm327) -> Result<()> {
+        self.execute(device, &TesterPresent())
+    }
+
+    pub fn close(self, device: &mut Elm327) -> Result<()> {
+        execute(device, &DiagnosticSessionControl(SessionType::Default))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use error_stack::Report;
+    use super::*;
+    use crate::elm327::EcuAddress;
+    use crate::elm327::capture::NoCapture;
+    use crate::elm327::transport::{self, Transport};
+    use crate::kia::simulator::{PackState, Simulator};
+    use crate::obd2::dtc::ClearDiagnosticInformation;
+
+    const BMS: EcuAddress = EcuAddress::standard(0x7E4, 0x7EC);
+
+    /// Answers the scripted commands in their order and passes the rest on to the simulator.
+    /// An answer to `AT MA` is what the monitor prints until it is stopped.
+    struct Scripted {
+        simulator: Simulator,
+        script: VecDeque<(&'static str, &'static str)>,
+        input: Vec<u8>,
+        output: VecDeque<u8>,
+        monitoring: bool,
+    }
+
+    impl Transport for Scripted {
+        fn init(&mut self) -> transport::Result<()> {
+            self.simulator.init()
+        }
+
+        fn write(&mut self, data: &[u8]) -> transport::Result<()> {
+            for b in data {
+                if self.monitoring {
+                    self.monitoring = false;
+                    self.output.extend(b"STOPPED\r\r>");
+                    continue;
+                }
+                match b {
+                    b'\r' => {}
+                    // it stops the simulator's monitor right after an `AT MA`
+                    b'\n' => {
+                        self.simulator.write(b"\n")?;
+                        continue;
+                    }
+                    _ => {
+                        self.input.push(*b);
+                        continue;
+                    }
+                }
+                let line = String::from_utf8_lossy(&self.input).to_string();
+                self.input.clear();
+                match self.script.front() {
+                    Some((command, answer)) if *command == line => {
+                        self.monitoring = line == "AT MA";
+                        self.output.extend(answer.as_bytes());
+                        self.script.pop_front();
+                    }
+                    _ => self.simulator.write(format!("{}\r", line).as_bytes())?,
+                }
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, data: &mut [u8]) -> transport::Result<usize> {
+            if self.output.is_empty() {
+                return self.simulator.read(data);
+            }
+            let len = data.len().min(self.output.len());
+            for (i, b) in self.output.drain(..len).enumerate() {
+                data[i] = b;
+            }
+            Ok(len)
+        }
+
+        fn connected(&self) -> bool {
+            self.simulator.connected()
+        }
+    }
+
+    fn elm327(script: &[(&'static str, &'static str)]) -> Elm327 {
+        let transport = Scripted {
+            simulator: Simulator::new(PackState::default()),
+            script: script.iter().copied().collect(),
+            input: Vec::new(),
+            output: VecDeque::new(),
+            monitoring: false,
+        };
+        let mut elm327 = Elm327::new(Box::new(transport), Box::new(NoCapture)).unwrap();
+        elm327.set_address(&BMS).unwrap();
+        elm327
+    }
+
+    fn rejection<T: fmt::Debug>(result: Result<T>) -> Error {
+        let e: Report<Error> = result.unwrap_err();
+        *e.current_context()
+    }
+
+    #[test]
+    fn negative_response_codes() {
+        for (code, expected) in [
+            (0x10, NegativeResponseCode::GeneralReject),
+            (0x11, NegativeResponseCode::ServiceNotSupported),
+            (0x12, NegativeResponseCode::SubFunctionNotSupported),
+            (0x13, NegativeResponseCode::IncorrectMessageLength),
+            (0x14, NegativeResponseCode::ResponseTooLong),
+            (0x21, NegativeResponseCode::BusyRepeatRequest),
+            (0x22, NegativeResponseCode::ConditionsNotCorrect),
+            (0x24, NegativeResponseCode::RequestSequenceError),
+            (0x31, NegativeResponseCode::RequestOutOfRange),
+            (0x33, NegativeResponseCode::SecurityAccessDenied),
+            (0x35, NegativeResponseCode::InvalidKey),
+            (0x36, NegativeResponseCode::ExceededNumberOfAttempts),
+            (0x37, NegativeResponseCode::RequiredTimeDelayNotExpired),
+            (0x78, NegativeResponseCode::ResponsePending),
+            (0x7E, NegativeResponseCode::SubFunctionNotSupportedInActiveSession),
+            (0x7F, NegativeResponseCode::ServiceNotSupportedInActiveSession),
+            (0x00, NegativeResponseCode::Other(0x00)),
+            (0x93, NegativeResponseCode::Other(0x93)),
+        ] {
+            assert_eq!(NegativeResponseCode::from(code), expected, "{:02X}", code);
+        }
+        assert_eq!(NegativeResponseCode::Other(0x93).to_string(), "code 93");
+    }
+
+    #[test]
+    fn clear_in_extended_session() {
+        // the simulated BMS answers response pending first when clearing in the extended session
+        let mut elm327 = elm327(&[]);
+        elm327.set_write_access(true);
+        let mut session = Session::open(&mut elm327, SessionType::Extended).unwrap();
+        assert_eq!(session.kind(), SessionType::Extended);
+        session.execute(&mut elm327, &ClearDiagnosticInformation()).unwrap();
+        session.keep_alive(&mut elm327).unwrap();
+        session.close(&mut elm327).unwrap();
+    }
+
+    #[test]
+    fn waits_for_pending_answer() {
+        let mut elm327 = elm327(&[
+            ("22 F1 90", "7EC 03 7F 22 78\r\r>"),
+            ("AT MA", "7EC 03 7F 22 78\r7EC 05 62 F1 90 41 42\r"),
+        ]);
+        assert_eq!(execute(&mut elm327, &ReadDataByIdentifier(0xF190)).unwrap(), b"AB".to_vec());
+    }
+
+    #[test]
+    fn pending_answer_after_another_pending() {
+        // the monitor is started again when the time limit runs out after a further pending answer
+        let mut elm327 = elm327(&[
+            ("22 F1 90", "7EC 03 7F 22 78\r\r>"),
+            ("AT MA", "7EC 03 7F 22 78\r"),
+            ("AT MA", "7EC 05 62 F1 90 41 42\r"),
+        ]);
+        assert_eq!(execute(&mut elm327, &ReadDataByIdentifier(0xF190)).unwrap(), b"AB".to_vec());
+    }
+
+    #[test]
+    fn pending_answer_times_out() {
+        // the monitor prints nothing, the ECU never answers
+        let mut elm327 = elm327(&[("22 F1 90", "7EC 03 7F 22 78\r\r>"), ("AT MA", "")]);
+        let started = Instant::now();
+        assert_eq!(rejection(execute(&mut elm327, &ReadDataByIdentifier(0xF190))), Error::PendingTimeout(0x22));
+        assert!(started.elapsed() >= RESPONSE_PENDING_TIMEOUT);
+    }
+
+    #[test]
+    fn retries_when_busy() {
+        let mut elm327 = elm327(&[
+            ("22 F1 90", "7EC 03 7F 22 21\r\r>"),
+            ("22 F1 90", "7EC 03 7F 22 21\r\r>"),
+            ("22 F1 90", "7EC 03 7F 22 21\r\r>"),
+            ("22 F1 90", "7EC 05 62 F1 90 41 42\r\r>"),
+        ]);
+        assert_eq!(execute(&mut elm327, &ReadDataByIdentifier(0xF190)).unwrap(), b"AB".to_vec());
+    }
+
+    #[test]
+    fn busy_retry_limit() {
+        let busy = ("22 F1 90", "7EC 03 7F 22 21\r\r>");
+        // one more busy answer than retries, the scripted answer after them is never asked for
+        let mut elm327 = elm327(&[busy, busy, busy, busy, ("22 F1 90", "7EC 05 62 F1 90 41 42\r\r>")]);
+        assert_eq!(
+            rejection(execute(&mut elm327, &ReadDataByIdentifier(0xF190))),
+            Error::Rejected { service: 0x22, code: NegativeResponseCode::BusyRepeatRequest }
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut elm327 = elm327(&[("22 F1 90", "7EC 03 7F 22 31\r\r>")]);
+        assert_eq!(
+            rejection(execute(&mut elm327, &ReadDataByIdentifier(0xF190))),
+            Error::Rejected { service: 0x22, code: NegativeResponseCode::RequestOutOfRange }
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+
+use crate::kia::Kia;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// Something the user asked for, goes ahead of the polling
+    High,
+    Normal,
+}
+
+type Job = Box<dyn FnOnce(&mut Kia) + Send>;
+
+#[derive(Default)]
+struct Queue {
+    high: VecDeque<Job>,
+    normal: VecDeque<Job>,
+    closed: bool,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Job> {
+        self.high.pop_front().or_else(|| self.normal.pop_front())
+    }
+}
+
+/// Owns the connection on a thread of its own, so a slow answer from the car never holds up the
+/// app. Jobs run one at a time, high priority ones first. Dropping the worker closes the
+/// connection and cancels the job in progress.
+pub struct Worker {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    cancel: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    /// How often the worker checks on the diagnostic session, in between jobs or while idle
+    const KEEP_ALIVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn spawn(kia: Kia) -> Self {
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+        let cancel = kia.cancel_handle();
+        let thread = {
+            let queue = queue.clone();
+            thread::spawn(move || Self::run(kia, queue))
+        };
+        Self { queue, cancel, thread: Some(thread) }
+    }
+
+    /// Closes the connection like dropping the worker does and waits until the connection is
+    /// gone, so its port can be opened again
+    pub fn close(mut self) {
+        let thread = self.thread.take();
+        drop(self);
+        if let Some(thread) = thread {
+            if thread.join().is_err() {
+                warn!("Worker thread panicked");
+            }
+        }
+    }
+
+    /// Queues `f`, its result comes through the receiver. The sender is dropped without a result
+    /// when the worker is closed first.
+    pub fn submit<T: Send + 'static>(&self, priority: Priority, f: impl FnOnce(&mut Kia) -> T + Send + 'static) -> mpsc::Receiver<T> {
+        let (reply, receiver) = mpsc::channel();
+        let job: Job = Box::new(move |kia| {
+            // nobody may be waiting anymore
+            let _ = reply.send(f(kia));
+        });
+
+        let (queue, available) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        if !queue.closed {
+            match priority {
+                Priority::High => queue.high.push_back(job),
+                Priority::Normal => queue.normal.push_back(job),
+            }
+            available.notify_one();
+        }
+        receiver
+    }
+
+    fn run(mut kia: Kia, queue: Arc<(Mutex<Queue>, Condvar)>) {
+        let (queue, available) = &*queue;
+        // a busy queue mustn't keep the session from being kept alive, the check is due after
+        // whatever job runs past it
+        let mut next_keep_alive = Instant::now() + Self::KEEP_ALIVE_CHECK_INTERVAL;
+        loop {
+            let job = {
+                let mut queue = queue.lock().unwrap();
+                loop {
+                    if queue.closed {
+                        debug!("Worker closed, dropping the connection");
+                        return;
+                    }
+                    if let Some(job) = queue.pop() {
+                        break Some(job);
+                    }
+                    let timeout = next_keep_alive.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        break None;
+                    }
+                    queue = available.wait_timeout(queue, timeout).unwrap().0;
+                }
+            };
+
+            if let Some(job) = job {
+                job(&mut kia);
+            }
+            if Instant::now() >= next_keep_alive {
+                if let Err(e) = kia.keep_alive() {
+                    warn!("Keep alive failed: {:?}", e);
+                }
+                next_keep_alive = Instant::now() + Self::KEEP_ALIVE_CHECK_INTERVAL;
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let (queue, available) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        queue.closed = true;
+        // waiting callers see their reply channel close
+        queue.high.clear();
+        queue.normal.clear();
+        self.cancel.store(true, Ordering::Relaxed);
+        available.notify_one();
+    }
+}
@@ -1,24 +1,39 @@
 import BatteryCells from "components/BatteryCells.tsx";
 import Connect from "components/Connect.tsx";
 import Chart from "components/Chart.tsx";
+import CanMonitor from "components/CanMonitor.tsx";
+import Obd2Values from "components/Obd2Values.tsx";
+import FaultCodes from "components/FaultCodes.tsx";
 import {useCarInfoHistory} from "contexts/CarInfoHistory.tsx";
 import {save, open} from "@tauri-apps/api/dialog";
 import {readTextFile, writeFile} from "@tauri-apps/api/fs";
 import {useSelectedHistoryElement} from "contexts/SelectedHistoryElement.ts";
 import {useTranslation} from "react-i18next";
+import {useConnection} from "contexts/Connection.tsx";
+import {BatteryHealth, BatteryInfo} from "models/CarInfo.ts";
 
 function App() {
     //async set resizeable
     const {t} = useTranslation();
-    const {fetch, setFetch} = useCarInfoHistory();
+    const {fetch, setFetch, lowVoltage} = useCarInfoHistory();
 
     const {carInfoHistory, setCarInfoHistory} = useCarInfoHistory();
+    const {connectedDevice} = useConnection();
     const [selectedHistoryElement] = useSelectedHistoryElement();
-    let cellVoltages = Array.from({length: 96}, () => 0);
+    let cellVoltages = Array.from({length: connectedDevice?.profile.cell_count ?? 96}, () => 0);
+    let auxBatteryVoltage: number | null = null;
+    let batteryHealth: BatteryHealth | null = null;
+    let batteryInfo: BatteryInfo | null = null;
     if (selectedHistoryElement) {
+        batteryInfo = carInfoHistory[selectedHistoryElement].battery_info;
         cellVoltages = carInfoHistory[selectedHistoryElement].battery_info.cell_voltages;
+        auxBatteryVoltage = carInfoHistory[selectedHistoryElement].aux_battery_voltage;
+        batteryHealth = carInfoHistory[selectedHistoryElement].battery_health ?? null;
     } else if (carInfoHistory.length > 0) {
+        batteryInfo = carInfoHistory[carInfoHistory.length - 1].battery_info;
         cellVoltages = carInfoHistory[carInfoHistory.length - 1].battery_info.cell_voltages;
+        auxBatteryVoltage = carInfoHistory[carInfoHistory.length - 1].aux_battery_voltage;
+        batteryHealth = carInfoHistory[carInfoHistory.length - 1].battery_health ?? null;
     }
     const saveHistory = async () => {
         const filePath = await save({
@@ -28,7 +43,9 @@ function App() {
             }]
         });
         if (filePath) {
-            console.log(await writeFile(filePath, JSON.stringify(carInfoHistory), {}));
+            // the vehicle says which car the history came from when files are shared
+            const history = {vehicle: connectedDevice?.vehicle ?? null, car_info: carInfoHistory};
+            console.log(await writeFile(filePath, JSON.stringify(history), {}));
         }
     }
     const loadHistory = async () => {
@@ -41,7 +58,10 @@ function App() {
         });
         if (typeof selected == "string") {
             readTextFile(selected).then((content) => {
-                let data = JSON.parse(content).map((carInfo: any) => {
+                const history = JSON.parse(content);
+                // older files only contain the car info list
+                const carInfo = Array.isArray(history) ? history : history.car_info;
+                let data = carInfo.map((carInfo: any) => {
                     carInfo.time = new Date(carInfo.time);
                     return carInfo;
                 });
@@ -59,6 +79,7 @@ function App() {
             <div className={"flex flex-row px-2 py-2"}>
                 <div className={"flex-grow min-w-0"}>
                     <Chart/>
+                    <FaultCodes/>
                 </div>
                 <div className={"flex flex-col gap-[10px]"}>
                     <div className="flex flex-col gap-[3px]">
@@ -72,11 +93,43 @@ function App() {
                         <button className="btn btn-sm btn-primary" disabled={carInfoHistory.length > 0}
                                 onClick={clearHistory}>{t("clear")}</button>
                     </div>
+                    <div className="text-sm">
+                        {t("aux_battery_voltage")}: {auxBatteryVoltage?.toFixed(1) ?? "-"} V
+                        <div>{t("state_of_health")}: {batteryHealth?.state_of_health.toFixed(1) ?? "-"} %</div>
+                        {batteryHealth &&
+                            <div className="opacity-60">
+                                {t("deterioration", {
+                                    max: batteryHealth.max_deterioration.toFixed(1),
+                                    maxCell: batteryHealth.max_deterioration_cell,
+                                    min: batteryHealth.min_deterioration.toFixed(1),
+                                    minCell: batteryHealth.min_deterioration_cell,
+                                })}
+                            </div>}
+                        {batteryInfo?.lifetime_throughput &&
+                            <div>{t("lifetime_throughput", {
+                                charge: batteryInfo.lifetime_throughput.charge.toFixed(0),
+                                energy: batteryInfo.lifetime_throughput.energy.toFixed(0),
+                            })}</div>}
+                        {batteryInfo?.since_connected &&
+                            <div className="opacity-60">{t("energy_since_connected", {
+                                charged: batteryInfo.since_connected.energy_charged.toFixed(1),
+                                discharged: batteryInfo.since_connected.energy_discharged.toFixed(1),
+                            })}</div>}
+                        {batteryInfo?.other_signals?.map((signal) => {
+                            return <div key={signal.name}>
+                                {t("signals." + signal.name, {defaultValue: signal.name.replace(/_/g, " ")})}: {Math.round(signal.value * 100) / 100} {signal.unit}
+                            </div>
+                        })}
+                        {lowVoltage !== null &&
+                            <div className="text-warning">{t("low_voltage_notice", {voltage: lowVoltage.toFixed(1)})}</div>}
+                    </div>
                     <div>
-                        <BatteryCells cellVoltages={cellVoltages}></BatteryCells>
+                        <BatteryCells cellVoltages={cellVoltages} profile={connectedDevice?.profile.id}></BatteryCells>
                     </div>
                 </div>
             </div>
+            <Obd2Values/>
+            <CanMonitor/>
         </>
     );
 }
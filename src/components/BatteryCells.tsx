@@ -2,20 +2,38 @@ import CellsBlock from "./CellsBlock.tsx";
 
 type ConnectProps = {
     cellVoltages: number[];
+    profile?: string;
     onCellClick?: (cellNumber: number) => void;
 }
-export default function BatteryCells({cellVoltages}: ConnectProps) {
+
+// how the cells sit in the Soul EV pack
+const SOUL_EV_BLOCKS = [
+    [[35, 48], [49, 62]],
+    [[25, 34], [63, 72]],
+    [[15, 24], [73, 82]],
+    [[1, 14], [83, 96]],
+]
+
+// other packs are shown in rows of 28 cells, in number order
+function rowBlocks(cellCount: number) {
+    const blocks = [];
+    for (let start = 1; start <= cellCount; start += 28) {
+        const end = Math.min(start + 27, cellCount);
+        // a block shows its cells in pairs, so the first half gets the odd pair
+        const middle = start + 2 * Math.ceil((end - start + 1) / 4);
+        blocks.push([[start, middle - 1], [middle, end]]);
+    }
+    return blocks;
+}
+
+export default function BatteryCells({cellVoltages, profile}: ConnectProps) {
     const avgVoltage = cellVoltages.reduce((a, b) => a + b, 0) / cellVoltages.length
     const avgDiffFromAvg = cellVoltages.map(value => Math.abs(value - avgVoltage)).reduce((a, b) => a + b, 0) / cellVoltages.length
     const badVoltage = avgVoltage - avgDiffFromAvg;
     const goodVoltage = avgVoltage + avgDiffFromAvg;
 
-    let cellBlocks = [
-        [[35, 48], [49, 62]],
-        [[25, 34], [63, 72]],
-        [[15, 24], [73, 82]],
-        [[1, 14], [83, 96]],
-    ]
+    const soulEv = (profile ?? "kia_soul_ev") == "kia_soul_ev" && cellVoltages.length == 96;
+    const cellBlocks = soulEv ? SOUL_EV_BLOCKS : rowBlocks(cellVoltages.length);
     return <div>
         <div className={"flex flex-col  gap-5"}>
             <p>
@@ -0,0 +1,116 @@
+import {useEffect, useState} from "react";
+import {tauri} from "@tauri-apps/api";
+import {listen} from "@tauri-apps/api/event";
+import {useTranslation} from "react-i18next";
+import {useConnection} from "contexts/Connection.tsx";
+import {CanFrame, MonitorSettings} from "models/CanFrame.ts";
+import {CommandError} from "models/CommandError.ts";
+
+type FrameStats = {
+    frame: CanFrame;
+    count: number;
+    changed: boolean[];
+}
+
+const hex = (value: number, digits: number) => value.toString(16).toUpperCase().padStart(digits, "0");
+
+const parseHex = (value: string) => {
+    const parsed = parseInt(value, 16);
+    return isNaN(parsed) ? null : parsed;
+}
+
+export default function CanMonitor() {
+    const {t} = useTranslation();
+    const {connectedDevice} = useConnection();
+    const [running, setRunning] = useState<boolean>(false);
+    const [filter, setFilter] = useState<string>("");
+    const [mask, setMask] = useState<string>("7FF");
+    const [frames, setFrames] = useState<Map<number, FrameStats>>(new Map());
+    const [error, setError] = useState<CommandError | null>(null);
+
+    useEffect(() => {
+        const unlistenFrames = listen<CanFrame[]>("can_frames", (event) => {
+            setFrames((previous) => {
+                const updated = new Map(previous);
+                for (const frame of event.payload) {
+                    const stats = updated.get(frame.id);
+                    updated.set(frame.id, {
+                        frame,
+                        count: (stats?.count ?? 0) + 1,
+                        changed: frame.data.map((b, i) => stats !== undefined && stats.frame.data[i] !== b),
+                    });
+                }
+                return updated;
+            });
+        });
+        const unlistenStopped = listen<CommandError | null>("can_monitor_stopped", (event) => {
+            setRunning(false);
+            setError(event.payload);
+        });
+        return () => {
+            unlistenFrames.then((unlisten) => unlisten());
+            unlistenStopped.then((unlisten) => unlisten());
+        }
+    }, []);
+
+    const start = async () => {
+        const settings: MonitorSettings = {
+            target: {type: "all"},
+            filter: parseHex(filter),
+            mask: parseHex(mask),
+        };
+        setError(null);
+        setFrames(new Map());
+        try {
+            await tauri.invoke("start_can_monitor", {settings});
+            setRunning(true);
+        } catch (e) {
+            setError(e as CommandError);
+        }
+    }
+    const stop = async () => {
+        await tauri.invoke("stop_can_monitor", {});
+        setRunning(false);
+    }
+
+    return <div className="card w-full card-compact bg-base-100 shadow-xl">
+        <div className="card-body">
+            <div className="flex flex-row items-center gap-2">
+                <span className="font-bold">{t("can_monitor")}</span>
+                {t("filter")}
+                <input type="text" value={filter} disabled={running} placeholder="7EC"
+                       onChange={(e) => setFilter(e.target.value)}
+                       className="input input-sm input-bordered w-20"/>
+                {t("mask")}
+                <input type="text" value={mask} disabled={running}
+                       onChange={(e) => setMask(e.target.value)}
+                       className="input input-sm input-bordered w-20"/>
+                <button className="btn btn-sm btn-primary" disabled={!connectedDevice}
+                        onClick={running ? stop : start}>{running ? t("stop") : t("start")}</button>
+                {error && <span className="text-error">{t("errors." + error.code, {defaultValue: error.message})}</span>}
+            </div>
+            <table className="table table-xs font-mono">
+                <thead>
+                <tr>
+                    <th>ID</th>
+                    <th>{t("data")}</th>
+                    <th>{t("count")}</th>
+                    <th>{t("time")}</th>
+                </tr>
+                </thead>
+                <tbody>
+                {Array.from(frames.values()).sort((a, b) => a.frame.id - b.frame.id).map((stats) => {
+                    return <tr key={stats.frame.id}>
+                        <td>{hex(stats.frame.id, 3)}</td>
+                        <td>{stats.frame.data.map((b, i) => {
+                            return <span key={i} className={stats.changed[i] ? "text-warning mr-1" : "mr-1"}>{hex(b, 2)}</span>
+                        })}</td>
+                        <td>{stats.count}</td>
+                        <td>{stats.frame.t.toFixed(2)}</td>
+                    </tr>
+                })}
+                </tbody>
+            </table>
+        </div>
+    </div>
+}
\ No newline at end of file
@@ -20,7 +20,8 @@ export default function Chart() {
 
     let cellsDataset: ChartDataset<"line">[];
     try {
-        cellsDataset = Array.from({length: 96}, (_, index) => {
+        const cellCount = carInfoHistory.at(-1)?.battery_info.cell_voltages.length ?? 0;
+        cellsDataset = Array.from({length: cellCount}, (_, index) => {
             let data = carInfoHistory.map((carInfo) => {
                 return +carInfo.battery_info.cell_voltages[index].toFixed(2);
             });
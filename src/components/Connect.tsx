@@ -1,8 +1,15 @@
-import {useState} from "react";
+import {useEffect, useState} from "react";
 import {ConnectionMethod} from "models/ConnectionMethod.ts";
+import {CaptureSettings} from "models/CaptureSettings.ts";
+import {LowVoltageSettings} from "models/LowVoltageSettings.ts";
+import {PollingSettings} from "models/PollingSettings.ts";
+import {SignalGroup} from "models/Sample.ts";
+import {CommandError} from "models/CommandError.ts";
+import {ProfileInfo} from "models/ConnectionInfo.ts";
 import {useConnection} from "contexts/Connection.tsx";
 import {useTranslation} from "react-i18next";
 import {tauri} from "@tauri-apps/api";
+import {ask, open} from "@tauri-apps/api/dialog";
 
 type ConnectWiFiSettingsProps = {
     address: string
@@ -28,9 +35,14 @@ function ConnectSerialSettings({onPortChange}: ConnectSerialSettingsProps) {
     const {t} = useTranslation();
     const [serialDevices, setSerialDevices] = useState<string[]>([]);
 
-    tauri.invoke<string[]>("list_serial_devices", {}).then((devices) => {
-        setSerialDevices(devices);
-    })
+    useEffect(() => {
+        tauri.invoke<string[]>("list_serial_devices", {}).then((devices) => {
+            setSerialDevices(devices);
+            if (devices.length > 0) {
+                onPortChange(devices[0]);
+            }
+        })
+    }, []);
 
     return <div className={"flex flex-row items-center gap-2"}>
         {t("device")}
@@ -38,17 +50,188 @@ function ConnectSerialSettings({onPortChange}: ConnectSerialSettingsProps) {
             onPortChange(e.target.value);
         }}>
             {serialDevices.map((device) => {
-                return <option value={device}>{device}</option>
+                return <option key={device} value={device}>{device}</option>
+            })}
+        </select>
+    </div>
+}
+
+
+type ConnectReplaySettingsProps = {
+    path: string
+    onPathChange: (path: string) => void
+}
+
+function ConnectReplaySettings({path, onPathChange}: ConnectReplaySettingsProps) {
+    const {t} = useTranslation();
+    const selectCapture = async () => {
+        const selected = await open({multiple: false});
+        if (typeof selected == "string") {
+            onPathChange(selected);
+        }
+    }
+
+    return <div className={"flex flex-row items-center gap-2"}>
+        <button className="btn btn-sm" onClick={selectCapture}>{t("capture")}</button>
+        <span className="truncate">{path}</span>
+    </div>
+}
+
+
+type VehicleProfileSelectProps = {
+    profile: string
+    onProfileChange: (profile: string) => void
+}
+
+function VehicleProfileSelect({profile, onProfileChange}: VehicleProfileSelectProps) {
+    const {t} = useTranslation();
+    const [profiles, setProfiles] = useState<ProfileInfo[]>([]);
+
+    useEffect(() => {
+        tauri.invoke<ProfileInfo[]>("list_vehicle_profiles", {}).then(setProfiles);
+    }, []);
+
+    return <div className={"flex flex-row items-center gap-2"}>
+        {t("vehicle_profile")}
+        <select className="select select-sm select-bordered w-full max-w-xs" value={profile} onChange={(e) => {
+            onProfileChange(e.target.value);
+        }}>
+            {profiles.map((profile) => {
+                return <option key={profile.id} value={profile.id}>
+                    {profile.experimental ? `${profile.name} (${t("experimental_profile")})` : profile.name}
+                </option>
             })}
         </select>
     </div>
 }
 
 
+function CaptureToggle() {
+    const {t} = useTranslation();
+    const [captureSettings, setCaptureSettings] = useState<CaptureSettings | null>(null);
+
+    useEffect(() => {
+        tauri.invoke<CaptureSettings>("get_capture_settings", {}).then(setCaptureSettings);
+    }, []);
+
+    const toggle = async (enabled: boolean) => {
+        if (!captureSettings) {
+            return;
+        }
+        const updated = {...captureSettings, enabled};
+        await tauri.invoke("set_capture_settings", {captureSettings: updated});
+        setCaptureSettings(updated);
+    }
+
+    return <label className="label cursor-pointer gap-2">
+        <span className="label-text">{t("record_session")}</span>
+        <input type="checkbox" className="checkbox checkbox-sm" disabled={!captureSettings}
+               checked={captureSettings?.enabled ?? false} onChange={(e) => toggle(e.target.checked)}/>
+    </label>
+}
+
+
+function LowVoltageProtection() {
+    const {t} = useTranslation();
+    const [settings, setSettings] = useState<LowVoltageSettings | null>(null);
+
+    useEffect(() => {
+        tauri.invoke<LowVoltageSettings>("get_low_voltage_settings", {}).then(setSettings);
+    }, []);
+
+    const update = async (changes: Partial<LowVoltageSettings>) => {
+        if (!settings) {
+            return;
+        }
+        const updated = {...settings, ...changes};
+        await tauri.invoke("set_low_voltage_settings", {settings: updated});
+        setSettings(updated);
+    }
+
+    return <label className="label cursor-pointer gap-2" title={t("low_voltage_protection_hint")}>
+        <input type="checkbox" className="checkbox checkbox-sm" disabled={!settings}
+               checked={settings?.enabled ?? false} onChange={(e) => update({enabled: e.target.checked})}/>
+        <span className="label-text">{t("low_voltage_protection")}</span>
+        <input type="number" className="input input-sm input-bordered w-20" step={0.1} min={10} max={13}
+               disabled={!settings?.enabled} value={settings?.threshold ?? ""}
+               onChange={(e) => update({threshold: +e.target.value})}/>
+        <span className="label-text">V</span>
+    </label>
+}
+
+
+const SIGNAL_GROUPS: SignalGroup[] = ["pack", "cell_voltages", "temperatures", "health"];
+
+function PollingIntervals() {
+    const {t} = useTranslation();
+    const [settings, setSettings] = useState<PollingSettings | null>(null);
+
+    useEffect(() => {
+        tauri.invoke<PollingSettings>("get_polling_settings", {}).then(setSettings);
+    }, []);
+
+    // seconds in the inputs, an empty one stops reading the group
+    const update = async (group: SignalGroup, seconds: string) => {
+        if (!settings) {
+            return;
+        }
+        const intervals = {...settings.intervals};
+        if (seconds == "" || +seconds <= 0) {
+            delete intervals[group];
+        } else {
+            intervals[group] = Math.round(+seconds * 1000);
+        }
+        const updated = {...settings, intervals};
+        await tauri.invoke("set_polling_settings", {settings: updated});
+        setSettings(updated);
+    }
+
+    return <div className="flex flex-row items-center gap-2" title={t("polling_intervals_hint")}>
+        <span className="label-text">{t("polling_intervals")}</span>
+        {SIGNAL_GROUPS.map((group) => {
+            const interval = settings?.intervals[group];
+            return <label key={group} className="flex flex-row items-center gap-1">
+                <span className="label-text">{t("signal_group." + group)}</span>
+                <input type="number" className="input input-sm input-bordered w-16" step={0.5} min={0}
+                       disabled={!settings} value={interval !== undefined ? interval / 1000 : ""}
+                       onChange={(e) => update(group, e.target.value)}/>
+            </label>
+        })}
+        <span className="label-text">{t("seconds")}</span>
+    </div>
+}
+
+
+function ExpertModeToggle() {
+    const {t} = useTranslation();
+    const [expertMode, setExpertMode] = useState<boolean>(false);
+
+    useEffect(() => {
+        tauri.invoke<boolean>("get_expert_mode", {}).then(setExpertMode);
+    }, []);
+
+    const toggle = async (enabled: boolean) => {
+        if (enabled && !await ask(t("expert_mode_confirm"), {type: "warning"})) {
+            return;
+        }
+        await tauri.invoke("set_expert_mode", {enabled});
+        setExpertMode(enabled);
+    }
+
+    return <label className="label cursor-pointer gap-2">
+        <span className="label-text">{t("expert_mode")}</span>
+        <input type="checkbox" className="checkbox checkbox-sm checkbox-warning"
+               checked={expertMode} onChange={(e) => toggle(e.target.checked)}/>
+    </label>
+}
+
+
 export default function Connect() {
     const {connect, disconnect, connecting, connectedDevice} = useConnection();
     const [connectionType, setConnectionMethod] = useState<ConnectionMethod>(ConnectionMethod.WIFI);
     const [connectionParam, setConnectionParam] = useState<string>("127.0.0.1:50059");//"192.168.0.10:35000");
+    const [profile, setProfile] = useState<string>("kia_soul_ev");
+    const [connectError, setConnectError] = useState<CommandError | null>(null);
     const {t} = useTranslation();
 
     const connectSettings: Record<ConnectionMethod, JSX.Element> = {
@@ -59,14 +242,20 @@ export default function Connect() {
             (value: string) => {
                 setConnectionParam(value);
             }
-        }/>
+        }/>,
+        [ConnectionMethod.DEMO]: <div>{t("demo_mode")}</div>,
+        [ConnectionMethod.REPLAY]: <ConnectReplaySettings path={connectionParam} onPathChange={(value: string) => {
+            setConnectionParam(value);
+        }}/>,
     }
 
     const handleConnect = () => {
-        connect(connectionType, connectionParam).then((connectedDevice) => {
-            console.log("we connected to " + connectedDevice);
-        }).catch((e) => {
+        setConnectError(null);
+        connect(connectionType, connectionParam, profile).then((connectedDevice) => {
+            console.log("we connected to " + connectedDevice.device_name);
+        }).catch((e: CommandError) => {
             console.log("we failed to connect: ", e);
+            setConnectError(e);
         });
     }
     const handleDisconnect = () => {
@@ -86,6 +275,14 @@ export default function Connect() {
                 setConnectionMethod(ConnectionMethod.SERIAL);
                 setConnectionParam("")
                 break;
+            case ConnectionMethod.DEMO.toString():
+                setConnectionMethod(ConnectionMethod.DEMO);
+                setConnectionParam("")
+                break;
+            case ConnectionMethod.REPLAY.toString():
+                setConnectionMethod(ConnectionMethod.REPLAY);
+                setConnectionParam("")
+                break;
 
         }
     }
@@ -95,13 +292,30 @@ export default function Connect() {
             <div className="card-body flex flex-row chil justify-start items-center">
                 <select className="select select-sm select-bordered w-full max-w-xs" onChange={updateConnectionMethod}>
                     <option value={ConnectionMethod.WIFI}>WiFi</option>
-                    <option disabled={true} value={ConnectionMethod.SERIAL}>Serial</option>
+                    <option value={ConnectionMethod.SERIAL}>Serial</option>
+                    <option value={ConnectionMethod.DEMO}>Demo</option>
+                    <option value={ConnectionMethod.REPLAY}>Replay</option>
                 </select>
                 <div className={"grow flex items-center "}>
                     {connectSettings[connectionType]}
                 </div>
+                <VehicleProfileSelect profile={profile} onProfileChange={setProfile}/>
+                <CaptureToggle/>
+                <LowVoltageProtection/>
+                <PollingIntervals/>
+                <ExpertModeToggle/>
                 <div>
-                    {connectedDevice && <span>{t('connected_to')} {connectedDevice}</span>}
+                    {connectedDevice && <span>{t('connected_to')} {connectedDevice.device_name}</span>}
+                    {connectedDevice?.vehicle.vin &&
+                        <span className="ml-2 opacity-60" title={connectedDevice.vehicle.bms_software_version ?? undefined}>
+                            {connectedDevice.vehicle.vin} {connectedDevice.vehicle.model_year}
+                        </span>}
+                    {connectedDevice?.profile.experimental &&
+                        <span className="badge badge-warning ml-2" title={t("experimental_profile_hint")}>{t("experimental_profile")}</span>}
+                    {connectedDevice?.capabilities.likely_clone &&
+                        <span className="badge badge-warning ml-2" title={t("likely_clone_hint")}>{t("likely_clone")}</span>}
+                    {!connectedDevice && connectError &&
+                        <span className="text-error">{t("errors." + connectError.code, {defaultValue: connectError.message})}</span>}
                 </div>
                 <div className="card-actions justify-self-end">
                     {
@@ -0,0 +1,71 @@
+import {useState} from "react";
+import {tauri} from "@tauri-apps/api";
+import {ask} from "@tauri-apps/api/dialog";
+import {useTranslation} from "react-i18next";
+import {useConnection} from "contexts/Connection.tsx";
+import {Dtc, DtcStatus} from "models/Dtc.ts";
+import {CommandError} from "models/CommandError.ts";
+
+const statusLabels = (status: DtcStatus) => {
+    return (Object.keys(status) as (keyof DtcStatus)[]).filter((key) => status[key]);
+}
+
+export default function FaultCodes() {
+    const {t} = useTranslation();
+    const {connectedDevice} = useConnection();
+    const [dtcs, setDtcs] = useState<Dtc[] | null>(null);
+    const [busy, setBusy] = useState<boolean>(false);
+    const [error, setError] = useState<CommandError | null>(null);
+
+    const read = async () => {
+        setBusy(true);
+        setError(null);
+        try {
+            setDtcs(await tauri.invoke<Dtc[]>("get_dtcs", {}));
+        } catch (e) {
+            setError(e as CommandError);
+        }
+        setBusy(false);
+    }
+    const clear = async () => {
+        if (!await ask(t("clear_dtcs_confirm"), {type: "warning"})) {
+            return;
+        }
+        setBusy(true);
+        setError(null);
+        try {
+            const token = await tauri.invoke<string>("request_dtc_clear_token", {});
+            await tauri.invoke("clear_dtcs", {token});
+            setDtcs(await tauri.invoke<Dtc[]>("get_dtcs", {}));
+        } catch (e) {
+            setError(e as CommandError);
+        }
+        setBusy(false);
+    }
+
+    return <div className="card w-full card-compact bg-base-100 shadow-xl">
+        <div className="card-body">
+            <div className="flex flex-row items-center gap-2">
+                <span className="font-bold">{t("fault_codes")}</span>
+                <button className="btn btn-sm btn-primary" disabled={!connectedDevice || busy}
+                        onClick={read}>{t("read")}</button>
+                <button className="btn btn-sm btn-error" disabled={!connectedDevice || busy || !dtcs?.length}
+                        onClick={clear}>{t("clear")}</button>
+                {error && <span className="text-error">{t("errors." + error.code, {defaultValue: error.message})}</span>}
+                {dtcs?.length === 0 && <span className="opacity-60">{t("no_fault_codes")}</span>}
+            </div>
+            {dtcs && dtcs.length > 0 && <table className="table table-xs">
+                <tbody>
+                {dtcs.map((dtc, i) => {
+                    return <tr key={i} className={dtc.ecu == "BMS" ? "text-warning" : ""}>
+                        <td className="font-mono">{dtc.code}{dtc.failure_type !== null && "-" + dtc.failure_type.toString(16).toUpperCase().padStart(2, "0")}</td>
+                        <td>{t("dtc_kind." + dtc.kind)}</td>
+                        <td>{dtc.ecu}</td>
+                        <td className="opacity-60">{dtc.status && statusLabels(dtc.status).map((label) => label.replace(/_/g, " ")).join(", ")}</td>
+                    </tr>
+                })}
+                </tbody>
+            </table>}
+        </div>
+    </div>
+}
\ No newline at end of file
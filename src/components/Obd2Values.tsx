@@ -0,0 +1,44 @@
+import {useState} from "react";
+import {tauri} from "@tauri-apps/api";
+import {useTranslation} from "react-i18next";
+import {useConnection} from "contexts/Connection.tsx";
+import {PidValue} from "models/PidValue.ts";
+import {CommandError} from "models/CommandError.ts";
+
+export default function Obd2Values() {
+    const {t} = useTranslation();
+    const {connectedDevice} = useConnection();
+    const [values, setValues] = useState<PidValue[]>([]);
+    const [loading, setLoading] = useState<boolean>(false);
+    const [error, setError] = useState<CommandError | null>(null);
+
+    const refresh = async () => {
+        setLoading(true);
+        setError(null);
+        try {
+            setValues(await tauri.invoke<PidValue[]>("get_obd2_values", {}));
+        } catch (e) {
+            setError(e as CommandError);
+        }
+        setLoading(false);
+    }
+
+    return <div className="card w-full card-compact bg-base-100 shadow-xl">
+        <div className="card-body">
+            <div className="flex flex-row items-center gap-2">
+                <span className="font-bold">{t("obd2_values")}</span>
+                <button className="btn btn-sm btn-primary" disabled={!connectedDevice || loading}
+                        onClick={refresh}>{t("refresh")}</button>
+                {error && <span className="text-error">{t("errors." + error.code, {defaultValue: error.message})}</span>}
+            </div>
+            <div className="flex flex-row flex-wrap gap-4">
+                {values.map((value) => {
+                    return <div key={value.pid}>
+                        <span className="opacity-60">{t("pids." + value.name, {defaultValue: value.name.replace(/_/g, " ")})}: </span>
+                        {Math.round(value.value * 100) / 100} {value.unit}
+                    </div>
+                })}
+            </div>
+        </div>
+    </div>
+}
\ No newline at end of file
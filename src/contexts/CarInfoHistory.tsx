@@ -1,5 +1,8 @@
-import {createContext, useContext, useEffect, useState} from 'react';
-import {CarInfo} from "models/CarInfo.ts";
+import {createContext, useContext, useEffect, useRef, useState} from 'react';
+import {tauri} from "@tauri-apps/api";
+import {listen} from "@tauri-apps/api/event";
+import {BatteryHealth, CarInfo} from "models/CarInfo.ts";
+import {Sample, SampleError} from "models/Sample.ts";
 import {useConnection} from "contexts/Connection.tsx";
 
 type CarInfoHisoryContextValue = {
@@ -9,6 +12,8 @@ type CarInfoHisoryContextValue = {
     clearCarInfo: () => void;
     fetch: boolean,
     setFetch: (fetch: boolean) => void;
+    // 12V battery voltage while polling is slowed down to protect it
+    lowVoltage: number | null;
 }
 
 export const CarInfoHistoryContext = createContext<CarInfoHisoryContextValue>({
@@ -21,7 +26,8 @@ export const CarInfoHistoryContext = createContext<CarInfoHisoryContextValue>({
     },
     fetch: false,
     setFetch: () => {
-    }
+    },
+    lowVoltage: null,
 });
 
 export const useCarInfoHistory = () => {
@@ -32,6 +38,7 @@ export const useCarInfoHistory = () => {
 export function CarInfoHistoryContextProvider({children}: { children: React.ReactNode }) {
     const [carInfo, setCarInfo] = useState<CarInfo[]>([]);
     const [fetch, setFetch] = useState<boolean>(false);
+    const [lowVoltage, setLowVoltage] = useState<number | null>(null);
     const addCarInfo = (carInfo: CarInfo) => {
         setCarInfo((prevCarInfo) => {
             return [...prevCarInfo, carInfo];
@@ -41,34 +48,90 @@ export function CarInfoHistoryContextProvider({children}: { children: React.Reac
         setCarInfo([]);
     }
 
-    const {connectedDevice, reconnect, getCarInfo} = useConnection()
+    const {connectedDevice, reconnect} = useConnection()
+    // the listeners outlive renders, they reconnect with the latest connection parameters
+    const reconnectRef = useRef(reconnect);
+    reconnectRef.current = reconnect;
+    const reconnecting = useRef<boolean>(false);
+    // groups come in at their own pace, a history entry is added with every pack reading
+    const latest = useRef<{ cellVoltages: number[] | null, moduleTemperatures: number[], health: BatteryHealth | null }>({
+        cellVoltages: null,
+        moduleTemperatures: [],
+        health: null,
+    });
+
+    useEffect(() => {
+        latest.current = {cellVoltages: null, moduleTemperatures: [], health: null};
+    }, [connectedDevice]);
+
     useEffect(() => {
-        if (connectedDevice) {
-            const interval = setInterval(async () => {
-                if (fetch) {
-                    try {
-                        let carInfo = await getCarInfo();
-                        console.log(carInfo);
-                        if(carInfo.battery_info.cell_voltages.some((v) => v == 0)){
-                            return
-                        }
+        tauri.invoke(fetch ? "start_polling" : "stop_polling", {}).catch((e) => console.log(e));
+    }, [fetch]);
 
-                        addCarInfo(carInfo);
-                    } catch (e) {
-                        console.log(e)
-                        clearInterval(interval);
-                        try {
-                            console.log("reconnecting")
-                            await reconnect()
-                        } catch (e) {
-                            console.log("reconnect failed")
-                        }
+    useEffect(() => {
+        const unlistenSample = listen<Sample>("car_sample", (event) => {
+            const sample = event.payload;
+            switch (sample.group) {
+                case "cell_voltages":
+                    if (!sample.values.some((v) => v == 0)) {
+                        latest.current.cellVoltages = sample.values;
+                    }
+                    break;
+                case "temperatures":
+                    latest.current.moduleTemperatures = sample.values;
+                    break;
+                case "health":
+                    latest.current.health = sample.values;
+                    break;
+                case "pack": {
+                    setLowVoltage(null);
+                    const cellVoltages = latest.current.cellVoltages;
+                    // the chart needs the cells, the first entry waits for them
+                    if (!cellVoltages) {
+                        return
                     }
+                    const {aux_battery_voltage, ...pack} = sample.values;
+                    addCarInfo({
+                        time: sample.time,
+                        aux_battery_voltage,
+                        battery_info: {
+                            ...pack,
+                            cell_voltages: cellVoltages,
+                            module_temperatures: latest.current.moduleTemperatures,
+                        },
+                        battery_health: latest.current.health,
+                    });
+                    break;
                 }
-            }, 1000)
-            return () => clearInterval(interval)
+            }
+        });
+        const unlistenError = listen<SampleError>("car_sample_error", async (event) => {
+            const error = event.payload.error;
+            console.log(event.payload);
+            // the backend skips reads to spare the 12V battery, nothing is wrong with the connection
+            if (error.code == "low_voltage") {
+                setLowVoltage(+(error.parameters?.[0] ?? 0));
+                return
+            }
+            // anything else is retried with the next read, unless the connection is gone
+            if ((error.code != "not_connected" && error.code != "internal_error") || reconnecting.current) {
+                return
+            }
+            reconnecting.current = true;
+            try {
+                console.log("reconnecting")
+                await reconnectRef.current()
+            } catch (e) {
+                console.log("reconnect failed")
+            } finally {
+                reconnecting.current = false;
+            }
+        });
+        return () => {
+            unlistenSample.then((unlisten) => unlisten());
+            unlistenError.then((unlisten) => unlisten());
         }
-    }, [connectedDevice, fetch])
+    }, [])
 
 
     return <CarInfoHistoryContext.Provider
@@ -79,5 +142,6 @@ export function CarInfoHistoryContextProvider({children}: { children: React.Reac
             setCarInfoHistory: setCarInfo,
             fetch,
             setFetch,
+            lowVoltage,
         }}>{children}</CarInfoHistoryContext.Provider>
 }
@@ -1,28 +1,27 @@
 import {createContext, useContext, useState} from 'react';
-import {CarInfo} from "models/CarInfo.ts";
 import {tauri} from "@tauri-apps/api";
 import {ConnectionMethod} from "models/ConnectionMethod.ts";
+import {ConnectionInfo} from "models/ConnectionInfo.ts";
 
 type ConnectionParams = {
     connectionMethod: ConnectionMethod,
     connectionParam: string
+    profile: string
 }
 type ConnectionContextValue = {
     connecting: boolean,
-    connectedDevice: String | null,
-    connect: (connectionMethod: ConnectionMethod, params: string) => Promise<string>;
+    connectedDevice: ConnectionInfo | null,
+    connect: (connectionMethod: ConnectionMethod, params: string, profile: string) => Promise<ConnectionInfo>;
     disconnect: () => Promise<void>;
-    reconnect: () => Promise<string>;
-    getCarInfo: () => Promise<CarInfo>;
+    reconnect: () => Promise<ConnectionInfo>;
 }
 
 export const ConnectionContext = createContext<ConnectionContextValue>({
     connecting: false,
     connectedDevice: null,
-    connect: (_cm: ConnectionMethod, _params: any) => Promise.reject("context not initialized"),
+    connect: (_cm: ConnectionMethod, _params: any, _profile: string) => Promise.reject("context not initialized"),
     disconnect: () => Promise.reject("context not initialized"),
     reconnect: () => Promise.reject("context not initialized"),
-    getCarInfo: () => Promise.reject("context not initialized"),
 });
 
 export const useConnection = () => {
@@ -31,22 +30,24 @@ export const useConnection = () => {
 
 export function ConnectionContextProvider({children}: { children: React.ReactNode }) {
     let [connecting, setConnecting] = useState<boolean>(false)
-    let [connectedDevice, setConnectedDevice] = useState<string | null>(null)
+    let [connectedDevice, setConnectedDevice] = useState<ConnectionInfo | null>(null)
     let [lastConnectionParams, setLastConnectionParams] = useState<ConnectionParams | null>(null)
     const value = {
         connecting,
         connectedDevice: connectedDevice,
-        connect: async (cm: ConnectionMethod, _params: string) => {
+        connect: async (cm: ConnectionMethod, _params: string, profile: string) => {
             setConnecting(true);
             setConnectedDevice(null);
             setLastConnectionParams({
                 connectionMethod: cm,
-                connectionParam: _params
+                connectionParam: _params,
+                profile
             })
             try {
-                const connectedDevice: string = await tauri.invoke<string>("connect", {
+                const connectedDevice = await tauri.invoke<ConnectionInfo>("connect", {
                     connectionMethod: cm,
-                    connectionParam: _params
+                    connectionParam: _params,
+                    profile
                 });
                 console.log("we connected to ", connectedDevice);
                 setConnecting(false)
@@ -69,13 +70,10 @@ export function ConnectionContextProvider({children}: { children: React.ReactNod
         },
         reconnect: async () => {
             if (lastConnectionParams) {
-                return await value.connect(lastConnectionParams.connectionMethod, lastConnectionParams.connectionParam);
+                return await value.connect(lastConnectionParams.connectionMethod, lastConnectionParams.connectionParam, lastConnectionParams.profile);
             }
             return Promise.reject("No previous connection");
         },
-        getCarInfo: async () => {
-            return await tauri.invoke<CarInfo>("get_car_info", {});
-        },
     }
 
     return <ConnectionContext.Provider
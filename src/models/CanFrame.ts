@@ -0,0 +1,16 @@
+export type CanFrame = {
+    t: number;
+    id: number;
+    data: number[];
+}
+
+export type MonitorTarget =
+    { type: "all" } |
+    { type: "receiver", address: number } |
+    { type: "transmitter", address: number };
+
+export type MonitorSettings = {
+    target: MonitorTarget;
+    filter: number | null;
+    mask: number | null;
+}
\ No newline at end of file
@@ -0,0 +1,5 @@
+export type CaptureSettings = {
+    enabled: boolean;
+    directory: string | null;
+    max_files: number;
+}
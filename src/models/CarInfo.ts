@@ -1,3 +1,23 @@
+export type CumulativeCounters = {
+    charge_current: number;
+    discharge_current: number;
+    energy_charged: number;
+    energy_discharged: number;
+    operating_time: number;
+}
+
+export type Throughput = {
+    charge: number;
+    energy: number;
+}
+
+// a value from the signal definitions the app doesn't know about
+export type SignalValue = {
+    name: string;
+    unit: string;
+    value: number;
+}
+
 export type BatteryInfo = {
     charge_level: number;
     charging: boolean;
@@ -10,9 +30,35 @@ export type BatteryInfo = {
     motor_speed: number;
     cell_voltages: number[];
     module_temperatures: number[]
+    // missing in histories saved before they were read
+    available_charge_power?: number;
+    available_discharge_power?: number;
+    max_cell_number?: number;
+    min_cell_number?: number;
+    fan_mode?: number;
+    fan_speed?: number;
+    isolation_resistance?: number | null;
+    counters?: CumulativeCounters | null;
+    lifetime_throughput?: Throughput | null;
+    since_connected?: CumulativeCounters | null;
+    other_signals?: SignalValue[];
+}
+
+export type BatteryHealth = {
+    state_of_health: number;
+    max_deterioration: number;
+    max_deterioration_cell: number;
+    min_deterioration: number;
+    min_deterioration_cell: number;
+    inlet_temperature: number | null;
+    heater_temperatures: number[] | null;
+    other_signals?: SignalValue[];
 }
 
 export type CarInfo = {
     time: number;
+    aux_battery_voltage: number | null;
     battery_info: BatteryInfo;
+    // missing in histories saved before it was read
+    battery_health?: BatteryHealth | null;
 }
\ No newline at end of file
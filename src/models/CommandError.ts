@@ -0,0 +1,5 @@
+export type CommandError = {
+    code: string;
+    message: string;
+    parameters: string[] | null;
+}
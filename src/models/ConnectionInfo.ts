@@ -0,0 +1,36 @@
+export type AdapterCapabilities = {
+    identifier: string;
+    version: string | null;
+    description: string | null;
+    stn_identifier: string | null;
+    stn_device: string | null;
+    receive_address_filter: boolean;
+    can_auto_format: boolean;
+    long_messages: boolean;
+    monitor_all: boolean;
+    likely_clone: boolean;
+}
+
+export type VehicleIdentity = {
+    vin: string | null;
+    model_year: number | null;
+    calibration_ids: string[];
+    ecu_names: string[];
+    bms_part_number: string | null;
+    bms_software_version: string | null;
+}
+
+export type ProfileInfo = {
+    id: string;
+    name: string;
+    cell_count: number;
+    module_count: number;
+    experimental: boolean;
+}
+
+export type ConnectionInfo = {
+    device_name: string;
+    capabilities: AdapterCapabilities;
+    vehicle: VehicleIdentity;
+    profile: ProfileInfo;
+}
\ No newline at end of file
@@ -1,5 +1,7 @@
 
 export enum ConnectionMethod {
     WIFI = 'wifi',
-    SERIAL = 'serial'
+    SERIAL = 'serial',
+    DEMO = 'demo',
+    REPLAY = 'replay'
 }
\ No newline at end of file
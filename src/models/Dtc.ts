@@ -0,0 +1,18 @@
+export type DtcStatus = {
+    test_failed: boolean;
+    test_failed_this_operation_cycle: boolean;
+    pending: boolean;
+    confirmed: boolean;
+    test_not_completed_since_last_clear: boolean;
+    test_failed_since_last_clear: boolean;
+    test_not_completed_this_operation_cycle: boolean;
+    warning_indicator_requested: boolean;
+}
+
+export type Dtc = {
+    code: string;
+    kind: "stored" | "pending" | "permanent" | "manufacturer";
+    ecu: string | null;
+    failure_type: number | null;
+    status: DtcStatus | null;
+}
\ No newline at end of file
@@ -0,0 +1,5 @@
+export type LowVoltageSettings = {
+    enabled: boolean;
+    threshold: number;
+    interval: number;
+}
\ No newline at end of file
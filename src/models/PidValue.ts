@@ -0,0 +1,6 @@
+export type PidValue = {
+    pid: number;
+    name: string;
+    unit: string;
+    value: number;
+}
\ No newline at end of file
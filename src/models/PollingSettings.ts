@@ -0,0 +1,6 @@
+import {SignalGroup} from "models/Sample.ts";
+
+export type PollingSettings = {
+    // milliseconds, groups without an interval aren't read
+    intervals: Partial<Record<SignalGroup, number>>;
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+import {BatteryHealth, BatteryInfo} from "models/CarInfo.ts";
+import {CommandError} from "models/CommandError.ts";
+
+export type SignalGroup = "pack" | "cell_voltages" | "temperatures" | "health";
+
+export type PackValues = Omit<BatteryInfo, "cell_voltages" | "module_temperatures"> & {
+    aux_battery_voltage: number | null;
+}
+
+export type Sample = { time: number } & (
+    { group: "pack", values: PackValues } |
+    { group: "cell_voltages", values: number[] } |
+    { group: "temperatures", values: number[] } |
+    { group: "health", values: BatteryHealth });
+
+export type SampleError = {
+    group: SignalGroup;
+    error: CommandError;
+}
\ No newline at end of file